@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::GAMEPAD_DEADZONE;
+use crate::debug::Console;
+use crate::settings::{AimMode, Settings};
+
+/// Something the player can do, independent of the button it is bound to
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    /// Hold to show where the ball would go without charging
+    Aim,
+    /// Hold to charge, release to launch
+    Fire,
+    Restart,
+    NextLevel,
+    Quit,
+    Pause,
+    /// Hold and move the mouse to drag the view around
+    CameraPan,
+    /// Plays the last shot again once it is over
+    Replay,
+    /// Puts the level back as it was before the last shot
+    Undo,
+    /// Keyboard aiming
+    AngleUp,
+    AngleDown,
+    PowerUp,
+    PowerDown,
+}
+
+impl Action {
+    pub const ALL: [Action; 13] = [
+        Action::Aim,
+        Action::Fire,
+        Action::Restart,
+        Action::NextLevel,
+        Action::Quit,
+        Action::Pause,
+        Action::CameraPan,
+        Action::Replay,
+        Action::Undo,
+        Action::AngleUp,
+        Action::AngleDown,
+        Action::PowerUp,
+        Action::PowerDown,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::Aim => "Aim",
+            Action::Fire => "Charge / Fire",
+            Action::Restart => "Restart",
+            Action::NextLevel => "Next level",
+            Action::Quit => "Quit",
+            Action::Pause => "Pause",
+            Action::CameraPan => "Pan camera",
+            Action::Replay => "Instant replay",
+            Action::Undo => "Undo shot",
+            Action::AngleUp => "Aim higher",
+            Action::AngleDown => "Aim lower",
+            Action::PowerUp => "More power",
+            Action::PowerDown => "Less power",
+        }
+    }
+
+    /// Fire is on the keyboard too when aiming with it
+    fn default_binding(&self, aim_mode: AimMode) -> Binding {
+        match self {
+            Action::Aim => Binding::Mouse(MouseButton::Left),
+            Action::Fire if aim_mode == AimMode::Keyboard => Binding::Key(KeyCode::Space),
+            Action::Fire => Binding::Mouse(MouseButton::Right),
+            Action::Restart => Binding::Key(KeyCode::R),
+            Action::NextLevel => Binding::Key(KeyCode::N),
+            Action::Quit => Binding::Key(KeyCode::Q),
+            Action::Pause => Binding::Key(KeyCode::P),
+            Action::CameraPan => Binding::Mouse(MouseButton::Middle),
+            Action::Replay => Binding::Key(KeyCode::T),
+            Action::Undo => Binding::Key(KeyCode::U),
+            Action::AngleUp => Binding::Key(KeyCode::Up),
+            Action::AngleDown => Binding::Key(KeyCode::Down),
+            Action::PowerUp => Binding::Key(KeyCode::Right),
+            Action::PowerDown => Binding::Key(KeyCode::Left),
+        }
+    }
+
+    fn default_gamepad_button(&self) -> Option<GamepadButtonType> {
+        match self {
+            Action::Aim => Some(GamepadButtonType::LeftTrigger2),
+            Action::Fire => Some(GamepadButtonType::RightTrigger2),
+            Action::Restart => Some(GamepadButtonType::West),
+            Action::NextLevel => Some(GamepadButtonType::South),
+            Action::Quit => Some(GamepadButtonType::East),
+            Action::Pause => Some(GamepadButtonType::Start),
+            Action::Replay => Some(GamepadButtonType::North),
+            Action::Undo => Some(GamepadButtonType::LeftTrigger),
+            Action::AngleUp => Some(GamepadButtonType::DPadUp),
+            Action::AngleDown => Some(GamepadButtonType::DPadDown),
+            Action::PowerUp => Some(GamepadButtonType::DPadRight),
+            Action::PowerDown => Some(GamepadButtonType::DPadLeft),
+            Action::CameraPan => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+/// How a binding is shown in on-screen hints, e.g. `"r"` or `"right click"`
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{}", format!("{:?}", key).to_lowercase()),
+            Binding::Mouse(MouseButton::Other(n)) => write!(f, "mouse {}", n),
+            Binding::Mouse(button) => {
+                write!(f, "{} click", format!("{:?}", button).to_lowercase())
+            }
+        }
+    }
+}
+
+/// The binding of every action. Actions missing from the config file keep
+/// their default binding
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Bindings(BTreeMap<Action, Binding>);
+
+impl Bindings {
+    pub fn get(&self, action: Action, aim_mode: AimMode) -> Binding {
+        self.0
+            .get(&action)
+            .copied()
+            .unwrap_or_else(|| action.default_binding(aim_mode))
+    }
+
+    pub fn set(&mut self, action: Action, binding: Binding) {
+        self.0.insert(action, binding);
+    }
+}
+
+/// Gamepad buttons for each action, used alongside the keyboard and mouse
+/// bindings. Only editable in the config file
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct GamepadBindings(BTreeMap<Action, GamepadButtonType>);
+
+impl GamepadBindings {
+    pub fn get(&self, action: Action) -> Option<GamepadButtonType> {
+        self.0
+            .get(&action)
+            .copied()
+            .or_else(|| action.default_gamepad_button())
+    }
+}
+
+/// State of every action this frame, read by gameplay systems instead of
+/// the raw keyboard and mouse
+#[derive(Default)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    just_released: HashSet<Action>,
+    /// Direction of the left stick, kept after the stick is let go so the
+    /// aim holds still. Cleared as soon as the mouse moves
+    pub stick_aim: Option<Vec2>,
+}
+
+impl Actions {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        self.just_released.contains(&action)
+    }
+}
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Actions>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_actions.after(InputSystem));
+    }
+}
+
+/// Whether `button` was just pressed on any connected gamepad
+pub fn gamepad_just_pressed(
+    gamepads: &Gamepads,
+    buttons: &Input<GamepadButton>,
+    button: GamepadButtonType,
+) -> bool {
+    gamepads
+        .iter()
+        .any(|pad| buttons.just_pressed(GamepadButton::new(*pad, button)))
+}
+
+fn update_actions(
+    settings: Res<Settings>,
+    keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<Input<GamepadButton>>,
+    pad_axes: Res<Axis<GamepadAxis>>,
+    mut cursor_moved: EventReader<CursorMoved>,
+    mut actions: ResMut<Actions>,
+    console: Option<Res<Console>>,
+) {
+    let actions = actions.as_mut();
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+    actions.just_released.clear();
+
+    if console.is_some() {
+        return;
+    }
+
+    for action in Action::ALL {
+        let (mut pressed, mut just_pressed, mut just_released) = match settings.binding(action) {
+            Binding::Key(key) => (
+                keyboard.pressed(key),
+                keyboard.just_pressed(key),
+                keyboard.just_released(key),
+            ),
+            Binding::Mouse(button) => (
+                mouse.pressed(button),
+                mouse.just_pressed(button),
+                mouse.just_released(button),
+            ),
+        };
+
+        if let Some(button) = settings.gamepad_bindings.get(action) {
+            for pad in gamepads.iter() {
+                let button = GamepadButton::new(*pad, button);
+                pressed |= pad_buttons.pressed(button);
+                just_pressed |= pad_buttons.just_pressed(button);
+                just_released |= pad_buttons.just_released(button);
+            }
+        }
+
+        if pressed {
+            actions.pressed.insert(action);
+        }
+        if just_pressed {
+            actions.just_pressed.insert(action);
+        }
+        if just_released {
+            actions.just_released.insert(action);
+        }
+    }
+
+    for pad in gamepads.iter() {
+        let axis = |axis_type| {
+            pad_axes
+                .get(GamepadAxis::new(*pad, axis_type))
+                .unwrap_or(0.)
+        };
+        let stick = Vec2::new(
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        );
+        if stick.length() > GAMEPAD_DEADZONE {
+            actions.stick_aim = Some(stick.normalize());
+        }
+    }
+
+    if cursor_moved.iter().next().is_some() {
+        actions.stick_aim = None;
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::game::{BlockMaterial, BodyId, CurrentLevel, JointId, Target};
+use crate::read_levels::{BodyRef, JointData, LevelData, TargetData, TowerData};
+use crate::terrain::Terrain;
+
+/// The level as the world has it now: every block where it lies, the targets
+/// still standing and the joints still holding. Everything that doesn't move
+/// is copied from the level being played
+pub fn capture_level(world: &mut World) -> LevelData {
+    let level = match world.get_resource::<CurrentLevel>() {
+        Some(level) => level.0.clone(),
+        None => return LevelData::default(),
+    };
+    let origin = world.resource::<Terrain>().origin();
+
+    let mut blocks: Vec<(BodyId, Transform, Vec2, &'static str)> = world
+        .query::<(&BodyId, &Transform, &Collider, &BlockMaterial)>()
+        .iter(world)
+        .filter_map(|(id, trans, collider, mat)| {
+            let size = collider.as_cuboid()?.half_extents() * 2.;
+            Some((*id, *trans, size, mat.0.name))
+        })
+        .collect();
+    blocks.sort_by_key(|(id, ..)| id.0);
+
+    // targets spawned from the console have no id and go after the level's
+    let mut targets: Vec<(Option<BodyId>, Transform)> = world
+        .query_filtered::<(Option<&BodyId>, &Transform), With<Target>>()
+        .iter(world)
+        .map(|(id, trans)| (id.copied(), *trans))
+        .collect();
+    targets.sort_by_key(|(id, _)| id.map_or(usize::MAX, |id| id.0));
+
+    // where each surviving body ends up in the new level
+    let mut refs = HashMap::new();
+    let tower = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, (id, trans, size, kind))| {
+            refs.insert(*id, BodyRef::Tower(i));
+            let corner = trans.translation.truncate() - origin - *size / 2.;
+            TowerData {
+                kind: kind.to_string(),
+                x: round(corner.x),
+                y: round(corner.y),
+                w: size.x,
+                h: size.y,
+                rotation: round(angle(trans).to_degrees()),
+            }
+        })
+        .collect();
+
+    let targets = targets
+        .iter()
+        .enumerate()
+        .map(|(i, (id, trans))| {
+            let original = id.and_then(|id| {
+                refs.insert(id, BodyRef::Target(i));
+                level.targets.get(id.0.checked_sub(level.tower.len())?)
+            });
+            // one on a patrol is wherever its route has taken it
+            if let Some(original) = original.filter(|target| target.path.is_some()) {
+                return original.clone();
+            }
+            let pos = trans.translation.truncate() - origin;
+            TargetData {
+                x: round(pos.x),
+                y: round(pos.y - 5.),
+                path: None,
+            }
+        })
+        .collect();
+
+    let bodies: HashMap<Entity, (BodyId, Transform)> = world
+        .query::<(Entity, &BodyId, &Transform)>()
+        .iter(world)
+        .map(|(ent, id, trans)| (ent, (*id, *trans)))
+        .collect();
+    // where each rope hangs from, and what that's hinged to
+    let swivels: HashMap<Entity, (Entity, Vec2)> = world
+        .query_filtered::<(Entity, &ImpulseJoint, &Transform), Without<JointId>>()
+        .iter(world)
+        .map(|(ent, joint, trans)| (ent, (joint.parent, trans.translation.truncate())))
+        .collect();
+    let mut joints: Vec<(JointId, JointData)> = world
+        .query::<(&JointId, &Parent, &ImpulseJoint)>()
+        .iter(world)
+        .filter_map(|(id, parent, joint)| {
+            let original = &level.joints[id.0];
+            let (a, trans) = bodies.get(&parent.get())?;
+            let (b, anchor) = match swivels.get(&joint.parent) {
+                Some(&(b, swivel)) => (b, swivel),
+                None => (
+                    joint.parent,
+                    trans
+                        .mul_vec3(joint.data.local_anchor2().extend(0.))
+                        .truncate(),
+                ),
+            };
+            let b = match original.b {
+                Some(_) => Some(*refs.get(&bodies.get(&b)?.0)?),
+                None => None,
+            };
+            let anchor = anchor - origin;
+            let data = JointData {
+                a: *refs.get(a)?,
+                b,
+                x: round(anchor.x),
+                y: round(anchor.y),
+                ..original.clone()
+            };
+            Some((*id, data))
+        })
+        .collect();
+    joints.sort_by_key(|(id, _)| id.0);
+
+    LevelData {
+        tower,
+        targets,
+        joints: joints.into_iter().map(|(_, joint)| joint).collect(),
+        ..level
+    }
+}
+
+/// Counter-clockwise turn about z, in radians
+fn angle(trans: &Transform) -> f32 {
+    trans.rotation.to_euler(EulerRot::ZYX).0
+}
+
+/// To the hundredth of a pixel or degree, to keep saved levels readable
+fn round(value: f32) -> f32 {
+    // adding zero turns -0 into 0
+    (value * 100.).round() / 100. + 0.
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::HeadlessLevel;
+    use crate::read_levels::{
+        BodyRef, JointData, LevelData, PathData, PointData, TargetData, TowerData,
+    };
+
+    fn block(kind: &str, x: f32, y: f32, w: f32, h: f32, rotation: f32) -> TowerData {
+        TowerData {
+            kind: kind.to_string(),
+            x,
+            y,
+            h,
+            w,
+            rotation,
+        }
+    }
+
+    #[test]
+    fn a_level_just_spawned_is_captured_as_written() {
+        let level = LevelData {
+            tower: vec![
+                block("wood1", -40., 0., 20., 80., 0.),
+                block("stone1", 20., 0., 20., 80., 0.),
+                block("wood2", -40., 80., 80., 20., 12.5),
+            ],
+            targets: vec![
+                TargetData {
+                    x: 0.,
+                    y: 0.,
+                    path: None,
+                },
+                TargetData {
+                    x: 100.,
+                    y: 150.,
+                    path: Some(PathData {
+                        points: vec![PointData { x: 50., y: 0. }],
+                        speed: 40.,
+                    }),
+                },
+            ],
+            joints: vec![
+                JointData {
+                    kind: "glue".to_string(),
+                    a: BodyRef::Tower(0),
+                    b: Some(BodyRef::Tower(2)),
+                    x: -30.,
+                    y: 80.,
+                    strength: Some(500.),
+                    limits: None,
+                    length: None,
+                },
+                JointData {
+                    kind: "hinge".to_string(),
+                    a: BodyRef::Tower(1),
+                    b: None,
+                    x: 30.,
+                    y: 0.,
+                    strength: None,
+                    limits: Some([-10., 10.]),
+                    length: None,
+                },
+                JointData {
+                    kind: "rope".to_string(),
+                    a: BodyRef::Target(0),
+                    b: None,
+                    x: 0.,
+                    y: 100.,
+                    strength: None,
+                    limits: None,
+                    length: Some(120.),
+                },
+            ],
+            terrain: vec![
+                PointData { x: -200., y: 0. },
+                PointData { x: -100., y: 30. },
+            ],
+            ..Default::default()
+        };
+
+        let captured = HeadlessLevel::spawn(&level).capture();
+        assert_eq!(
+            serde_json::to_value(&captured).unwrap(),
+            serde_json::to_value(&level).unwrap()
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::PathBuf;
+
+use crate::constants::{HEIGHT, LEVELS_FILE, WIDTH};
+
+pub const USAGE: &str = "\
+Usage: trash_the_tower [options]
+       trash_the_tower --solve
+       trash_the_tower --generate <seed> <count>
+
+Options:
+  --levels <path>      levels file (.json, .ron or .toml) or directory of
+                       one level per file, instead of assets/levels.json
+  --level <index>      skip the main menu and start at this level
+  --code <code>        play a shared level code
+  --mode <mode>        play (default), editor, verify, settle, share or
+                       replay
+  --shots <file>       record the shots taken to this file, or with
+                       --mode replay, play back the shots recorded in it
+  --size <w>x<h>       window size in pixels
+  --debug-physics      draw the Rapier colliders and joints
+  --dev                F3 debug overlay and developer console
+  --seed <seed>        seed for endless towers instead of a random one
+  --help               show this message
+  --solve              report the winning shots of every level
+  --generate <seed> <count>
+                       print <count> generated towers as a levels file";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Play,
+    /// Build a level, from `--level` or `--code` or from nothing
+    Editor,
+    /// Check every level is stable and can be won without opening a window
+    Verify,
+    /// Let every tower come to rest and print the levels as they end up
+    Settle,
+    /// Print a code for every level, to send to someone
+    Share,
+    /// Play back the shots recorded in `Options::shots` at their level
+    Replay,
+    /// Report the winning shots of every level
+    Solve,
+    /// Print `count` generated towers as JSON
+    Generate {
+        seed: u64,
+        count: usize,
+    },
+}
+
+/// How the game was started from the command line. Inserted as a resource
+/// before the plugins are added; the defaults give the usual game
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub levels: PathBuf,
+    /// Level to start at, straight into the game. Taken once it is used so
+    /// later runs from the main menu start at the beginning
+    pub level: Option<usize>,
+    /// A level code to play instead of the levels file
+    pub code: Option<String>,
+    pub mode: Mode,
+    pub width: f32,
+    pub height: f32,
+    pub debug_physics: bool,
+    /// Developer tools, the console included
+    pub dev: bool,
+    pub seed: Option<u64>,
+    /// Shot file to record to, or to play back in `Mode::Replay`
+    pub shots: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            levels: PathBuf::from(LEVELS_FILE),
+            level: None,
+            code: None,
+            mode: Mode::Play,
+            width: WIDTH,
+            height: HEIGHT,
+            debug_physics: false,
+            dev: false,
+            seed: None,
+            shots: None,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the arguments after the program name
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--levels" => options.levels = PathBuf::from(value()?),
+                "--level" => options.level = Some(parse_number(arg, value()?)?),
+                "--code" => options.code = Some(value()?.clone()),
+                "--mode" => {
+                    options.mode = match value()?.as_str() {
+                        "play" => Mode::Play,
+                        "editor" => Mode::Editor,
+                        "verify" | "headless-verify" => Mode::Verify,
+                        "settle" => Mode::Settle,
+                        "share" => Mode::Share,
+                        "replay" => Mode::Replay,
+                        other => return Err(format!("unknown mode \"{}\"", other)),
+                    }
+                }
+                "--size" => {
+                    let size = value()?;
+                    let (w, h) = size
+                        .split_once('x')
+                        .ok_or_else(|| format!("--size expects <w>x<h>, got \"{}\"", size))?;
+                    options.width = parse_number(arg, w)?;
+                    options.height = parse_number(arg, h)?;
+                    let valid = |v: f32| v > 0. && v.is_finite();
+                    if !(valid(options.width) && valid(options.height)) {
+                        return Err(format!("--size must be positive, got \"{}\"", size));
+                    }
+                }
+                "--debug-physics" => options.debug_physics = true,
+                "--dev" => options.dev = true,
+                "--seed" => options.seed = Some(parse_number(arg, value()?)?),
+                "--shots" => options.shots = Some(PathBuf::from(value()?)),
+                "--solve" => options.mode = Mode::Solve,
+                "--generate" => {
+                    let seed = parse_number(arg, value()?)?;
+                    let count = parse_number(arg, value()?)?;
+                    options.mode = Mode::Generate { seed, count };
+                }
+                other => return Err(format!("unknown argument \"{}\"", other)),
+            }
+        }
+
+        if options.mode == Mode::Replay && options.shots.is_none() {
+            return Err("--mode replay needs --shots <file>".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got \"{}\"", arg, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn no_arguments_play_the_usual_game() {
+        let options = parse("").unwrap();
+        assert_eq!(options.mode, Mode::Play);
+        assert_eq!(options.levels, PathBuf::from(LEVELS_FILE));
+        assert_eq!((options.width, options.height), (WIDTH, HEIGHT));
+        assert!(options.level.is_none() && !options.dev);
+    }
+
+    #[test]
+    fn reads_every_argument() {
+        let options = parse(
+            "--levels towers --level 3 --code TTT1-abc --mode settle --size 1024x768 \
+             --debug-physics --dev --seed 99 --shots run.json",
+        )
+        .unwrap();
+        assert_eq!(options.levels, PathBuf::from("towers"));
+        assert_eq!(options.level, Some(3));
+        assert_eq!(options.code.as_deref(), Some("TTT1-abc"));
+        assert_eq!(options.mode, Mode::Settle);
+        assert_eq!((options.width, options.height), (1024., 768.));
+        assert!(options.debug_physics && options.dev);
+        assert_eq!(options.seed, Some(99));
+        assert_eq!(options.shots, Some(PathBuf::from("run.json")));
+
+        assert_eq!(parse("--mode headless-verify").unwrap().mode, Mode::Verify);
+        assert_eq!(parse("--mode editor").unwrap().mode, Mode::Editor);
+        assert_eq!(
+            parse("--mode replay --shots run.json").unwrap().mode,
+            Mode::Replay
+        );
+        assert_eq!(parse("--solve").unwrap().mode, Mode::Solve);
+        assert_eq!(
+            parse("--generate 7 20").unwrap().mode,
+            Mode::Generate { seed: 7, count: 20 }
+        );
+    }
+
+    #[test]
+    fn explains_bad_arguments() {
+        assert_eq!(parse("--levels").unwrap_err(), "--levels needs a value");
+        assert_eq!(
+            parse("--level two").unwrap_err(),
+            "--level expects a number, got \"two\""
+        );
+        assert_eq!(parse("--mode fly").unwrap_err(), "unknown mode \"fly\"");
+        assert!(parse("--mode replay").unwrap_err().contains("--shots"));
+        assert!(parse("--size 800").unwrap_err().contains("<w>x<h>"));
+        assert!(parse("--size 0x600").unwrap_err().contains("positive"));
+        assert!(parse("--size 800xinf").unwrap_err().contains("positive"));
+        assert!(parse("--generate 7").unwrap_err().contains("needs a value"));
+        assert_eq!(parse("--fast").unwrap_err(), "unknown argument \"--fast\"");
+    }
+}
@@ -1,4 +1,8 @@
-use bevy::prelude::Color;
+use bevy::prelude::{Color, Vec2};
+
+pub const LEVELS_FILE: &str = "./assets/levels.json";
+// the level editor saves here, as a levels file of one level
+pub const EDITOR_LEVEL_FILE: &str = "./edited_level.json";
 
 pub const WIDTH: f32 = 900.0;
 pub const HEIGHT: f32 = 600.0;
@@ -7,41 +11,196 @@ pub const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
 pub const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
 pub const PRESSED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
 
+pub const PIXELS_PER_METER: f32 = 30.0;
+
+// developer overlay: velocity lines show this many seconds of travel
+pub const DEBUG_VELOCITY_SCALE: f32 = 0.25;
+pub const DEBUG_COLOR: Color = Color::rgb(0.9, 0.1, 0.7);
+
+// gamepad aiming: stick deflection ignored as drift, and how far from the
+// ball the aim reticle sits
+pub const GAMEPAD_DEADZONE: f32 = 0.3;
+pub const AIM_RETICLE_DISTANCE: f32 = 150.0;
+
+// keyboard aiming: one step per tap, repeating while the key is held
+pub const KEY_AIM_ANGLE_STEP: f32 = 1.0;
+pub const KEY_AIM_POWER_STEP: f32 = MAX_POWER / 100.0;
+pub const KEY_AIM_DEFAULT_POWER: f32 = MAX_POWER / 2.0;
+pub const KEY_AIM_REPEAT_DELAY: f32 = 0.3;
+pub const KEY_AIM_REPEAT: f32 = 0.03;
+
+// the level editor places blocks and targets on a grid this fine, blocks
+// starting at this size
+pub const EDITOR_GRID: f32 = 10.0;
+pub const EDITOR_BLOCK_SIZE: Vec2 = Vec2::new(20.0, 60.0);
+
+// how far the camera can be dragged from the center of the level
+pub const CAMERA_PAN_LIMIT: Vec2 = Vec2::new(300.0, 200.0);
+
 pub const LAUNCH_FACTOR: f32 = 2.0;
+pub const MAX_POWER: f32 = 70.0;
+pub const POWER_RATE: f32 = 0.75;
+// trajectory preview dots, spaced in seconds of flight
+pub const TRAJECTORY_DOTS: usize = 12;
+pub const TRAJECTORY_STEP: f32 = 0.1;
 pub const TARGET_FORCE_THRESH: f32 = 20.0;
-pub const TARGET_COLOR: (Color, Color) = (Color::rgb(0.82, 0.16, 0.16), Color::rgb(0.65, 0.11, 0.11));
+pub const TARGET_COLOR: (Color, Color) =
+    (Color::rgb(0.82, 0.16, 0.16), Color::rgb(0.65, 0.11, 0.11));
+
+// a shot ends once the ball has been this slow for this long, or times out
+pub const BALL_REST_SPEED: f32 = 5.0;
+pub const BALL_REST_TIME: f32 = 1.0;
+pub const SHOT_TIMEOUT: f32 = 10.0;
+
+pub const TARGET_POINTS: u32 = 1000;
+pub const BLOCK_POINTS: u32 = 100;
+pub const SHOT_BONUS: u32 = 500;
+// shots that can be taken back per level, and what each costs
+pub const UNDOS_PER_LEVEL: usize = 3;
+pub const UNDO_COST: u32 = 250;
+
+// endless mode loses a shot per tower every ENDLESS_SHOT_DECAY towers
+pub const ENDLESS_LIVES: u32 = 3;
+pub const ENDLESS_SHOTS: usize = 3;
+pub const ENDLESS_SHOT_DECAY: usize = 4;
+
+// impacts softer than this are silent, this hard or more play at full volume
+pub const IMPACT_SOUND_FORCE: f32 = 50.0;
+pub const IMPACT_FULL_FORCE: f32 = 1000.0;
+pub const MAX_IMPACT_SOUNDS: usize = 3;
+
+// glue between blocks gives way above this force, unless the level says otherwise
+pub const GLUE_STRENGTH: f32 = 400.0;
+// light enough not to drag on what hangs from it
+pub const ROPE_SWIVEL_MASS: f32 = 0.01;
+
+// particle effects, all drawn from one pool of entities
+pub const MAX_PARTICLES: usize = 150;
+pub const DEBRIS_FORCE: f32 = 200.0;
+pub const DUST_FORCE: f32 = 150.0;
+pub const PARTICLE_GRAVITY: f32 = -400.0;
+
+// slow motion on the ball's first heavy hit of a shot and on every target
+// destroyed, easing back to full speed over SLOWMO_DURATION real seconds
+pub const SLOWMO_SCALE: f32 = 0.25;
+pub const SLOWMO_DURATION: f32 = 1.2;
+pub const SLOWMO_IMPACT_FORCE: f32 = 150.0;
+// the instant replay shows this many seconds before the shot ended
+pub const REPLAY_SECONDS: f32 = 4.0;
+// a played back shot is fired once the ball has been ready this long
+pub const SHOT_LOG_DELAY: f32 = 1.0;
+
+// blocks up to this mass, as Rapier measures it, are light enough for the wind
+pub const WIND_MAX_BLOCK_MASS: f32 = 0.8;
+// zones with less gravity, more gravity, or only drag
+pub const ZONE_COLORS: [Color; 3] = [
+    Color::rgba(0.6, 0.8, 1.0, 0.25),
+    Color::rgba(0.5, 0.2, 0.6, 0.25),
+    Color::rgba(0.8, 0.8, 0.8, 0.3),
+];
+
+pub const SCORES_PATH: &str = "./scores.json";
+pub const SCORES_LOCK_RETRIES: usize = 100;
+pub const SCORES_LOCK_STALE: std::time::Duration = std::time::Duration::from_secs(10);
+pub const HIGH_SCORE_ENTRIES: usize = 10;
+pub const MAX_NAME_LEN: usize = 12;
+
+pub const SETTINGS_PATH: &str = "./settings.json";
+pub const SETTINGS_VERSION: u32 = 1;
+pub const UI_SCALES: [f32; 4] = [0.8, 1.0, 1.25, 1.5];
 
 pub const GROUND_HEIGHT: f32 = 15.0;
+// default world bounds, this far past the sides and bottom of the window
+// and this far above it
+pub const WORLD_MARGIN: f32 = 50.0;
+pub const WORLD_CEILING: f32 = 600.0;
 pub const GROUND_COLOR: Color = Color::DARK_GREEN;
+pub const ROPE_COLOR: Color = Color::rgb(0.3, 0.25, 0.15);
+pub const MOVER_COLOR: (Color, Color) = (Color::rgb(0.35, 0.42, 0.5), Color::rgb(0.25, 0.3, 0.36));
+
+// headless simulation, stepped at a fixed rate
+pub const HEADLESS_DT: f32 = 1.0 / 60.0;
+pub const HEADLESS_WARMUP_FRAMES: usize = 60;
+pub const HEADLESS_SHOT_FRAMES: usize = 600;
+
+// a tower is stable if nothing moves more than this while left alone
+pub const STABLE_FRAMES: usize = 180;
+pub const STABLE_MAX_DRIFT: f32 = 4.0;
+pub const STABLE_MAX_TILT: f32 = 0.05;
+// shared level codes, the version goes up whenever old codes can't be read
+pub const LEVEL_CODE_PREFIX: &str = "TTT";
+pub const LEVEL_CODE_VERSION: u32 = 1;
+pub const LEVEL_CODE_MAX_SIZE: usize = 1 << 20;
+
+// longest a tower is given to come to rest before it is played, and how far
+// a block can move while it does before the tower counts as collapsed
+pub const SETTLE_MAX_FRAMES: usize = 1200;
+pub const SETTLE_COLLAPSE_DRIFT: f32 = 20.0;
+pub const SETTLE_COLLAPSE_TILT: f32 = 0.3;
+
+// level solver sweep
+pub const SOLVER_MIN_ANGLE: f32 = -30.0;
+pub const SOLVER_MAX_ANGLE: f32 = 90.0;
+pub const SOLVER_ANGLE_STEP: f32 = 5.0;
+pub const SOLVER_POWER_STEP: f32 = 10.0;
+pub const SOLVER_MAX_SHOTS: usize = 3;
+
+// procedural towers
+pub const GEN_MAX_TOWER_WIDTH: f32 = 210.0;
+pub const GEN_COLUMN_WIDTH: f32 = 15.0;
+pub const GEN_BEAM_HEIGHT: f32 = 15.0;
+pub const GEN_MIN_BAY: f32 = 50.0;
+pub const GEN_MAX_BAY: f32 = 100.0;
+pub const GEN_MIN_STORY: f32 = 50.0;
+pub const GEN_MAX_STORY: f32 = 95.0;
+pub const GEN_MAX_ATTEMPTS: u64 = 20;
+
+/// How a material sounds when hit
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Impact {
+    Wood,
+    Stone,
+}
 
 pub struct Material {
+    /// The `kind` of a block made of it in the level files
+    pub name: &'static str,
     pub density: f32,
     pub color1: Color,
     pub color2: Color,
+    pub impact: Impact,
 }
 
 pub const WOOD1: Material = Material {
+    name: "wood1",
     density: 0.5,
     color1: Color::rgb(0.6, 0.4, 0.2),
     color2: Color::rgb(0.525, 0.349, 0.176),
+    impact: Impact::Wood,
 };
 
 pub const WOOD2: Material = Material {
+    name: "wood2",
     density: 1.0,
     color1: Color::rgb(0.451, 0.302, 0.149),
     color2: Color::rgb(0.376, 0.251, 0.125),
+    impact: Impact::Wood,
 };
 
 pub const STONE1: Material = Material {
+    name: "stone1",
     density: 1.5,
     // color1: Color::rgb(0.510, 0.510, 0.490),
     // color2: Color::rgb(0.459, 0.459, 0.439),
     color1: Color::rgb(0.408, 0.408, 0.392),
     color2: Color::rgb(0.357, 0.357, 0.341),
+    impact: Impact::Stone,
 };
 
 pub const STONE2: Material = Material {
+    name: "stone2",
     density: 2.0,
     color1: Color::rgb(0.306, 0.306, 0.294),
     color2: Color::rgb(0.255, 0.255, 0.243),
+    impact: Impact::Stone,
 };
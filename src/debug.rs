@@ -0,0 +1,354 @@
+use bevy::diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin};
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude as lyon;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::capture::capture_level;
+use crate::cli::Options;
+use crate::constants::{DEBUG_COLOR, DEBUG_VELOCITY_SCALE};
+use crate::game::{
+    spawn_target, Block, Game, GameMode, LevelState, Levels, LoadLevel, Run, SharedLevel, Target,
+};
+use crate::level_code::{copy_to_clipboard, encode_level};
+use crate::slowmo::TimeScale;
+use crate::terrain::Terrain;
+
+const HELP: &str =
+    "level <n> | spawn target <x> <y> | gravity [<x> <y>] | slowmo <factor> | save <file> | code";
+
+#[derive(Component)]
+struct OverlayNode;
+
+#[derive(Component)]
+struct OverlayText;
+
+#[derive(Component)]
+struct VelocityLines;
+
+#[derive(Component)]
+struct ConsoleNode;
+
+#[derive(Component)]
+struct ConsoleText;
+
+/// Present while the developer console is open. It takes the keyboard, so
+/// no action fires while typing
+pub struct Console {
+    input: String,
+    output: String,
+}
+
+/// Developer tools: F3 toggles an overlay with the Rapier colliders and
+/// contacts, body velocities and some counters, and the key below Esc opens
+/// a console. Only there with `--debug-physics`, the console only with
+/// `--dev` as its cheats would reach the high scores
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Options>();
+        let options = app.world.resource::<Options>();
+        let (debug_physics, dev) = (options.debug_physics, options.dev);
+        if !debug_physics && !dev {
+            return;
+        }
+
+        app.add_plugin(FrameTimeDiagnosticsPlugin)
+            .add_plugin(RapierDebugRenderPlugin {
+                mode: DebugRenderMode::default() | DebugRenderMode::CONTACTS,
+                ..default()
+            })
+            .insert_resource(DebugRenderContext {
+                enabled: debug_physics,
+                ..default()
+            })
+            .add_system(toggle_overlay)
+            .add_system(update_overlay);
+
+        if dev {
+            app.add_system(toggle_console)
+                .add_system(console_input.run_if_resource_exists::<Console>());
+        }
+    }
+}
+
+fn toggle_overlay(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    mut render_context: ResMut<DebugRenderContext>,
+    overlay: Query<Entity, With<OverlayNode>>,
+) {
+    if !keyboard.just_pressed(KeyCode::F3) {
+        return;
+    }
+
+    if !overlay.is_empty() {
+        for ent in overlay.iter() {
+            commands.entity(ent).despawn_recursive();
+        }
+        render_context.enabled = false;
+        return;
+    }
+
+    render_context.enabled = true;
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(70.),
+                    right: Val::Px(15.),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                    font_size: 20.0,
+                    color: DEBUG_COLOR,
+                },
+            ),
+            ..default()
+        })
+        .insert(OverlayNode)
+        .insert(OverlayText);
+
+    commands
+        .spawn_bundle(lyon::GeometryBuilder::build_as(
+            &lyon::shapes::Line(Vec2::ZERO, Vec2::ZERO),
+            lyon::DrawMode::Stroke(lyon::StrokeMode::new(DEBUG_COLOR, 1.5)),
+            Transform::from_xyz(0., 0., 50.),
+        ))
+        .insert(OverlayNode)
+        .insert(VelocityLines);
+}
+
+fn update_overlay(
+    diagnostics: Res<Diagnostics>,
+    game: Res<Game>,
+    rapier_config: Res<RapierConfiguration>,
+    entities: Query<Entity>,
+    bodies: Query<(&Transform, &Velocity)>,
+    targets: Query<&Target>,
+    blocks: Query<&Block>,
+    mut text: Query<&mut Text, With<OverlayText>>,
+    mut lines: Query<&mut lyon::Path, With<VelocityLines>>,
+) {
+    for mut text in text.iter_mut() {
+        let fps = diagnostics
+            .get(FrameTimeDiagnosticsPlugin::FPS)
+            .and_then(|fps| fps.average())
+            .unwrap_or(0.);
+        let time_scale = match rapier_config.timestep_mode {
+            TimestepMode::Variable { time_scale, .. }
+            | TimestepMode::Interpolated { time_scale, .. } => time_scale,
+            TimestepMode::Fixed { .. } => 1.,
+        };
+        let state = match game.state {
+            LevelState::Prelaunch => "prelaunch",
+            LevelState::Launched => "launched",
+            LevelState::Complete => "complete",
+            LevelState::LastLevelComplete => "last level complete",
+            LevelState::Failed => "failed",
+        };
+
+        text.sections[0].value = format!(
+            "FPS {:.0}\nEntities {}\nBodies {}\nTargets {}\nBlocks {}\nLevel {} {}\nTime scale {:.2}",
+            fps,
+            entities.iter().len(),
+            bodies.iter().len(),
+            targets.iter().len(),
+            blocks.iter().len(),
+            game.level,
+            state,
+            time_scale,
+        );
+    }
+
+    for mut path in lines.iter_mut() {
+        let mut builder = lyon::PathBuilder::new();
+        for (trans, vel) in bodies.iter() {
+            let pos = trans.translation.truncate();
+            builder.move_to(pos);
+            builder.line_to(pos + vel.linvel * DEBUG_VELOCITY_SCALE);
+        }
+        *path = builder.build();
+    }
+}
+
+fn toggle_console(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    console: Option<Res<Console>>,
+    nodes: Query<Entity, With<ConsoleNode>>,
+) {
+    if !keyboard.just_pressed(KeyCode::Grave) {
+        return;
+    }
+
+    if console.is_some() {
+        for ent in nodes.iter() {
+            commands.entity(ent).despawn_recursive();
+        }
+        commands.remove_resource::<Console>();
+        return;
+    }
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+        font_size: 20.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(0.),
+                    left: Val::Px(0.),
+                    ..default()
+                },
+                size: Size::new(Val::Percent(100.), Val::Px(60.)),
+                padding: UiRect::all(Val::Px(6.)),
+                ..default()
+            },
+            color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+            ..default()
+        })
+        .insert(ConsoleNode)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::from_section(format!("{}\n> _", HELP), style),
+                    ..default()
+                })
+                .insert(ConsoleText);
+        });
+
+    commands.insert_resource(Console {
+        input: String::new(),
+        output: HELP.to_string(),
+    });
+}
+
+fn console_input(
+    mut commands: Commands,
+    mut console: ResMut<Console>,
+    mut chars: EventReader<ReceivedCharacter>,
+    keyboard: Res<Input<KeyCode>>,
+    state: Res<CurrentState<AppState>>,
+    mut game: ResMut<Game>,
+    run: Res<Run>,
+    levels: Res<Levels>,
+    shared: Option<Res<SharedLevel>>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut time_scale: ResMut<TimeScale>,
+    terrain: Res<Terrain>,
+    mut load_events: EventWriter<LoadLevel>,
+    mut text: Query<&mut Text, With<ConsoleText>>,
+) {
+    for ev in chars.iter() {
+        // the key that opens the console
+        if !ev.char.is_control() && ev.char != '`' {
+            console.input.push(ev.char);
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::Back) {
+        console.input.pop();
+    }
+
+    if keyboard.just_pressed(KeyCode::Return) {
+        let line = std::mem::take(&mut console.input);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let numbers: Vec<f32> = words.iter().filter_map(|w| w.parse().ok()).collect();
+        let in_game = state.0 == AppState::InGame;
+
+        console.output = match (words.as_slice(), numbers.as_slice()) {
+            ([], _) => String::new(),
+            (["level", n], _) if in_game => {
+                // a shared level is played on its own, endless towers never run out
+                let count = shared.map_or(levels.0.len(), |_| 1);
+                match n.parse::<usize>() {
+                    Ok(n) if run.mode == GameMode::Endless || n < count => {
+                        load_events.send(LoadLevel(n));
+                        format!("loading level {}", n)
+                    }
+                    Ok(n) => format!("no level {}, they go from 0 to {}", n, count - 1),
+                    Err(_) => format!("\"{}\" isn't a level number", n),
+                }
+            }
+            (["spawn", "target", _, _], [x, y]) if in_game => {
+                let pos = terrain.origin() + Vec2::new(*x, *y);
+                let target = spawn_target(&mut commands, pos);
+                game.targets_left += 1;
+                // the shot has already switched collisions on for the others
+                if game.state == LevelState::Launched {
+                    commands
+                        .entity(target)
+                        .insert(ActiveEvents::CONTACT_FORCE_EVENTS);
+                }
+                format!("target at {}, {}", x, y)
+            }
+            (["save", file], _) if in_game => {
+                let path = file.to_string();
+                commands.add(move |world: &mut World| {
+                    let level = capture_level(world);
+                    let json = serde_json::to_string_pretty(&[level]).unwrap();
+                    let output = match std::fs::write(&path, json) {
+                        Ok(()) => format!("saved to {}", path),
+                        Err(err) => format!("can't write {}: {}", path, err),
+                    };
+                    if let Some(mut console) = world.get_resource_mut::<Console>() {
+                        console.output = output;
+                    }
+                });
+                format!("saving to {}", file)
+            }
+            (["code"], _) if in_game => {
+                commands.add(|world: &mut World| {
+                    let code = encode_level(&capture_level(world));
+                    // the console can't be copied from, the terminal can
+                    info!("level code: {}", code);
+                    let output = match copy_to_clipboard(&code) {
+                        Ok(()) => format!("level code copied to the clipboard: {}", code),
+                        Err(err) => format!("{}, it is in the log: {}", err, code),
+                    };
+                    if let Some(mut console) = world.get_resource_mut::<Console>() {
+                        console.output = output;
+                    }
+                });
+                String::new()
+            }
+            (["level", ..] | ["spawn", ..] | ["save", ..] | ["code"], _) if !in_game => {
+                "not in a game".to_string()
+            }
+            (["gravity"], _) => {
+                let gravity = rapier_config.gravity;
+                format!("gravity {}, {} px/s²", gravity.x, gravity.y)
+            }
+            (["gravity", _, _], [x, y]) => {
+                rapier_config.gravity = Vec2::new(*x, *y);
+                format!("gravity {}, {} px/s²", x, y)
+            }
+            (["slowmo", _], [factor]) if *factor > 0. => {
+                time_scale.base = *factor;
+                format!("time scale {}", factor)
+            }
+            _ => format!("unknown command \"{}\". {}", line.trim(), HELP),
+        };
+    }
+
+    if console.is_changed() {
+        for mut text in text.iter_mut() {
+            text.sections[0].value = format!("{}\n> {}_", console.output, console.input);
+        }
+    }
+}
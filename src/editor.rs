@@ -0,0 +1,468 @@
+use std::fs;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::cli::Options;
+use crate::constants::{
+    Material, EDITOR_BLOCK_SIZE, EDITOR_GRID, EDITOR_LEVEL_FILE, HEIGHT, STONE1, STONE2, WIDTH,
+    WOOD1, WOOD2,
+};
+use crate::game::{
+    cursor_world_position, spawn_level_bodies, GameCamera, GameMode, LevelNode, Levels, Run,
+    SharedLevel,
+};
+use crate::level_code::{
+    copy_to_clipboard, decode_level, encode_level, modifier_pressed, paste_from_clipboard,
+};
+use crate::read_levels::{validate_level, BodyRef, LevelData, TargetData, TowerData};
+use crate::terrain::Terrain;
+
+const HELP: &str = "\
+Left click: place  Right click: remove
+1-4: block  T: target  Arrows: size
+Ctrl+C/Ctrl+V: copy/paste level code
+Ctrl+S: save  Enter: play  Esc: menu";
+
+/// The level being built, kept while it is playtested
+pub struct EditedLevel(pub LevelData);
+
+/// Present while a level from the editor is played, to go back to it
+/// afterwards
+pub struct Playtest;
+
+/// What a left click places
+#[derive(Clone, Copy)]
+enum Tool {
+    Block(&'static Material),
+    Target,
+}
+
+struct Editor {
+    tool: Tool,
+    size: Vec2,
+    status: String,
+}
+
+#[derive(Component)]
+struct EditorNode;
+
+#[derive(Component)]
+struct StatusText;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(AppState::Editor, setup_editor)
+            .add_exit_system(AppState::Editor, remove_editor)
+            .add_enter_system(AppState::MainMenu, back_to_editor)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::Editor)
+                    .with_system(show_level)
+                    .with_system(edit_level)
+                    .with_system(editor_keys)
+                    .with_system(update_status)
+                    .into(),
+            )
+            .add_system(
+                leave_playtest
+                    .run_in_state(AppState::InGame)
+                    .run_if_resource_exists::<Playtest>(),
+            );
+    }
+}
+
+fn setup_editor(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut options: ResMut<Options>,
+    levels: Res<Levels>,
+    edited: Option<Res<EditedLevel>>,
+    shared: Option<Res<SharedLevel>>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    // back from a playtest, or a level from a code, the command line or
+    // nothing at all
+    let level = match (edited, shared, options.level.take()) {
+        (Some(edited), _, _) => edited.0.clone(),
+        (None, Some(shared), _) => shared.0.clone(),
+        (None, None, Some(i)) => levels.0.get(i).cloned().unwrap_or_default(),
+        (None, None, None) => LevelData::default(),
+    };
+    commands.insert_resource(EditedLevel(level));
+    commands.remove_resource::<SharedLevel>();
+    commands.remove_resource::<Playtest>();
+    commands.insert_resource(Editor {
+        tool: Tool::Block(&WOOD1),
+        size: EDITOR_BLOCK_SIZE,
+        status: String::new(),
+    });
+    // the level is shown as it will start, not played
+    rapier_config.physics_pipeline_active = false;
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+        font_size: 24.0,
+        color: Color::BLACK,
+    };
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(5.),
+                    left: Val::Px(8.),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::from_section(HELP, style.clone()),
+            ..default()
+        })
+        .insert(EditorNode);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(5.),
+                    right: Val::Px(15.),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::from_section("", style),
+            ..default()
+        })
+        .insert(EditorNode)
+        .insert(StatusText);
+}
+
+fn remove_editor(
+    mut commands: Commands,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    nodes: Query<Entity, Or<(With<EditorNode>, With<LevelNode>)>>,
+) {
+    commands.remove_resource::<Editor>();
+    rapier_config.physics_pipeline_active = true;
+    for ent in nodes.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+}
+
+/// A playtest that ends at the main menu, its level done, goes back to the
+/// editor instead
+fn back_to_editor(mut commands: Commands, playtest: Option<Res<Playtest>>) {
+    if playtest.is_some() {
+        commands.insert_resource(NextState(AppState::Editor));
+    }
+}
+
+fn leave_playtest(mut commands: Commands, keyboard: Res<Input<KeyCode>>) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        commands.insert_resource(NextState(AppState::Editor));
+    }
+}
+
+/// Rebuilds the level's bodies whenever it is edited
+fn show_level(
+    mut commands: Commands,
+    edited: Res<EditedLevel>,
+    level_nodes: Query<Entity, With<LevelNode>>,
+) {
+    if !edited.is_changed() {
+        return;
+    }
+    for ent in level_nodes.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+    spawn_level_bodies(&mut commands, &edited.0, WIDTH, HEIGHT);
+}
+
+/// Places the current block or a target at the cursor with a left click and
+/// removes what is under it with a right click
+fn edit_level(
+    mut edited: ResMut<EditedLevel>,
+    editor: Res<Editor>,
+    mouse: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+) {
+    let place = mouse.just_pressed(MouseButton::Left);
+    let remove = mouse.just_pressed(MouseButton::Right);
+    if !place && !remove {
+        return;
+    }
+
+    let cursor = match (windows.get_primary(), cameras.get_single()) {
+        (Some(window), Ok((camera, camera_trans))) => {
+            cursor_world_position(window, camera, camera_trans)
+        }
+        _ => None,
+    };
+    let origin = Terrain::new(&edited.0, WIDTH, HEIGHT).origin();
+    let point = match cursor {
+        Some(cursor) => cursor - origin,
+        None => return,
+    };
+
+    if remove {
+        if let Some(body) = body_at(&edited.0, point) {
+            remove_body(&mut edited.0, body);
+        }
+        return;
+    }
+
+    let snap = |v: f32| (v / EDITOR_GRID).round() * EDITOR_GRID;
+    match editor.tool {
+        Tool::Block(mat) => edited.0.tower.push(TowerData {
+            kind: mat.name.to_string(),
+            x: snap(point.x - editor.size.x / 2.),
+            y: snap(point.y - editor.size.y / 2.),
+            w: editor.size.x,
+            h: editor.size.y,
+            rotation: 0.,
+        }),
+        // spawned 5 above where it is placed
+        Tool::Target => edited.0.targets.push(TargetData {
+            x: snap(point.x),
+            y: snap(point.y - 5.),
+            path: None,
+        }),
+    }
+}
+
+fn editor_keys(
+    mut commands: Commands,
+    mut editor: ResMut<Editor>,
+    mut edited: ResMut<EditedLevel>,
+    keyboard: Res<Input<KeyCode>>,
+) {
+    if modifier_pressed(&keyboard) {
+        if keyboard.just_pressed(KeyCode::S) {
+            editor.status = match save_level(&edited.0) {
+                Ok(()) => format!("Saved to {}", EDITOR_LEVEL_FILE),
+                Err(err) => err,
+            };
+        } else if keyboard.just_pressed(KeyCode::C) {
+            editor.status = match copy_to_clipboard(&encode_level(&edited.0)) {
+                Ok(()) => "Level code copied".to_string(),
+                Err(err) => err,
+            };
+        } else if keyboard.just_pressed(KeyCode::V) {
+            // the level pasted replaces the one being edited
+            match paste_from_clipboard().and_then(|code| decode_level(&code)) {
+                Ok(level) => {
+                    edited.0 = level;
+                    editor.status = "Level code pasted".to_string();
+                }
+                Err(err) => editor.status = err,
+            }
+        }
+        return;
+    }
+
+    for (key, tool) in [
+        (KeyCode::Key1, Tool::Block(&WOOD1)),
+        (KeyCode::Key2, Tool::Block(&WOOD2)),
+        (KeyCode::Key3, Tool::Block(&STONE1)),
+        (KeyCode::Key4, Tool::Block(&STONE2)),
+        (KeyCode::T, Tool::Target),
+    ] {
+        if keyboard.just_pressed(key) {
+            editor.tool = tool;
+        }
+    }
+
+    let mut size = editor.size;
+    for (key, step) in [
+        (KeyCode::Right, Vec2::X),
+        (KeyCode::Left, -Vec2::X),
+        (KeyCode::Up, Vec2::Y),
+        (KeyCode::Down, -Vec2::Y),
+    ] {
+        if keyboard.just_pressed(key) {
+            size = (size + step * EDITOR_GRID).max(Vec2::splat(EDITOR_GRID));
+        }
+    }
+    if size != editor.size {
+        editor.size = size;
+    }
+
+    if keyboard.just_pressed(KeyCode::Return) {
+        match validate_level(&edited.0) {
+            Ok(()) => {
+                commands.insert_resource(SharedLevel(edited.0.clone()));
+                commands.insert_resource(Run::new(GameMode::Campaign, None));
+                commands.insert_resource(Playtest);
+                commands.insert_resource(NextState(AppState::InGame));
+            }
+            Err(err) => editor.status = format!("Can't play: {}", err),
+        }
+    } else if keyboard.just_pressed(KeyCode::Escape) {
+        commands.remove_resource::<EditedLevel>();
+        commands.insert_resource(NextState(AppState::MainMenu));
+    }
+}
+
+fn update_status(editor: Res<Editor>, mut text: Query<&mut Text, With<StatusText>>) {
+    if !editor.is_changed() {
+        return;
+    }
+    let tool = match editor.tool {
+        Tool::Block(mat) => format!("{} {}x{}", mat.name, editor.size.x, editor.size.y),
+        Tool::Target => "target".to_string(),
+    };
+    for mut text in text.iter_mut() {
+        text.sections[0].value = format!("{}\n{}", tool, editor.status);
+    }
+}
+
+/// Writes the level as a levels file of its own, to play with `--levels`
+fn save_level(level: &LevelData) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(&[level]).unwrap();
+    fs::write(EDITOR_LEVEL_FILE, json)
+        .map_err(|err| format!("can't write {}: {}", EDITOR_LEVEL_FILE, err))
+}
+
+/// The target or block at `point`, in the level's own coordinates. Targets
+/// are drawn over the tower so are found first
+fn body_at(level: &LevelData, point: Vec2) -> Option<BodyRef> {
+    let target = level
+        .targets
+        .iter()
+        .rposition(|t| point.distance(Vec2::new(t.x, t.y + 5.)) <= 10.);
+    if let Some(i) = target {
+        return Some(BodyRef::Target(i));
+    }
+
+    level
+        .tower
+        .iter()
+        .rposition(|b| {
+            let size = Vec2::new(b.w, b.h);
+            let center = Vec2::new(b.x, b.y) + size / 2.;
+            let local = Vec2::from_angle(-b.rotation.to_radians()).rotate(point - center);
+            local.abs().cmple(size / 2.).all()
+        })
+        .map(BodyRef::Tower)
+}
+
+/// Takes a body out of the level along with the joints on it, keeping the
+/// other joints on the bodies they held
+fn remove_body(level: &mut LevelData, body: BodyRef) {
+    let on_body = |r: &BodyRef| match (*r, body) {
+        (BodyRef::Tower(a), BodyRef::Tower(b)) | (BodyRef::Target(a), BodyRef::Target(b)) => a == b,
+        _ => false,
+    };
+    level
+        .joints
+        .retain(|j| !on_body(&j.a) && !j.b.as_ref().is_some_and(on_body));
+
+    let shift = |r: &mut BodyRef| match (r, body) {
+        (BodyRef::Tower(a), BodyRef::Tower(b)) | (BodyRef::Target(a), BodyRef::Target(b))
+            if *a > b =>
+        {
+            *a -= 1
+        }
+        _ => {}
+    };
+    for joint in level.joints.iter_mut() {
+        shift(&mut joint.a);
+        if let Some(b) = joint.b.as_mut() {
+            shift(b);
+        }
+    }
+
+    match body {
+        BodyRef::Tower(i) => {
+            level.tower.remove(i);
+        }
+        BodyRef::Target(i) => {
+            level.targets.remove(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_levels::JointData;
+
+    fn block(x: f32) -> TowerData {
+        TowerData {
+            kind: "wood1".to_string(),
+            x,
+            y: 0.,
+            w: 20.,
+            h: 60.,
+            rotation: 0.,
+        }
+    }
+
+    fn glue(a: BodyRef, b: Option<BodyRef>) -> JointData {
+        JointData {
+            kind: "glue".to_string(),
+            a,
+            b,
+            x: 0.,
+            y: 0.,
+            strength: None,
+            limits: None,
+            length: None,
+        }
+    }
+
+    #[test]
+    fn finds_what_is_under_the_cursor() {
+        let level = LevelData {
+            tower: vec![block(0.), block(100.)],
+            targets: vec![TargetData {
+                x: 110.,
+                y: 60.,
+                path: None,
+            }],
+            ..default()
+        };
+
+        assert!(matches!(
+            body_at(&level, Vec2::new(10., 30.)),
+            Some(BodyRef::Tower(0))
+        ));
+        assert!(matches!(
+            body_at(&level, Vec2::new(110., 30.)),
+            Some(BodyRef::Tower(1))
+        ));
+        assert!(matches!(
+            body_at(&level, Vec2::new(110., 68.)),
+            Some(BodyRef::Target(0))
+        ));
+        assert!(body_at(&level, Vec2::new(50., 30.)).is_none());
+    }
+
+    #[test]
+    fn removing_a_block_keeps_the_other_joints_on_their_bodies() {
+        let mut level = LevelData {
+            tower: vec![block(0.), block(20.), block(40.)],
+            joints: vec![
+                glue(BodyRef::Tower(0), Some(BodyRef::Tower(1))),
+                glue(BodyRef::Tower(2), None),
+                glue(BodyRef::Target(0), Some(BodyRef::Tower(2))),
+            ],
+            ..default()
+        };
+
+        remove_body(&mut level, BodyRef::Tower(1));
+
+        assert_eq!(level.tower.len(), 2);
+        assert_eq!(level.tower[1].x, 40.);
+        assert_eq!(level.joints.len(), 2);
+        assert!(matches!(level.joints[0].a, BodyRef::Tower(1)));
+        assert!(matches!(level.joints[1].a, BodyRef::Target(0)));
+        assert!(matches!(level.joints[1].b, Some(BodyRef::Tower(1))));
+    }
+}
@@ -0,0 +1,196 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::constants::PIXELS_PER_METER;
+use crate::game::Paused;
+use crate::read_levels::{LevelData, WindData};
+
+/// Bodies pushed by the wind: the ball once it is launched and the lightest
+/// blocks of the tower
+#[derive(Component)]
+pub struct WindBlown;
+
+/// A zone of the level in world coordinates
+pub struct Zone {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub gravity: f32,
+    pub drag: f32,
+}
+
+/// Wind and zones of the level being played
+#[derive(Default)]
+pub struct Environment {
+    pub wind: Option<WindData>,
+    pub zones: Vec<Zone>,
+    /// Seconds of simulation since the level started, drives the gusts
+    pub time: f32,
+}
+
+impl Environment {
+    /// `origin` is where the level's coordinates start in the world
+    pub fn new(level: &LevelData, origin: Vec2) -> Self {
+        let zones = level
+            .zones
+            .iter()
+            .map(|zone| {
+                let min = origin + Vec2::new(zone.x, zone.y);
+                Zone {
+                    min,
+                    max: min + Vec2::new(zone.w, zone.h),
+                    gravity: zone.gravity,
+                    drag: zone.drag,
+                }
+            })
+            .collect();
+
+        Environment {
+            wind: level.wind.clone(),
+            zones,
+            time: 0.,
+        }
+    }
+
+    /// Wind acceleration `offset` seconds from now
+    pub fn wind(&self, offset: f32) -> Vec2 {
+        let wind = match &self.wind {
+            Some(wind) => wind,
+            None => return Vec2::ZERO,
+        };
+
+        // two waves out of step so the gusts don't repeat too evenly
+        let phase = (self.time + offset) / wind.gust_period.max(0.1);
+        let swing = 0.6 * (phase * TAU).sin() + 0.4 * (phase * TAU * 2.3 + 1.).sin();
+        Vec2::new(wind.x, wind.y) * (1. + wind.gust * swing)
+    }
+
+    /// Gravity multiplier and drag at `pos`, the last zone listed wins
+    /// where they overlap
+    pub fn zone_at(&self, pos: Vec2) -> (f32, f32) {
+        self.zones
+            .iter()
+            .rev()
+            .find(|zone| pos.cmpge(zone.min).all() && pos.cmple(zone.max).all())
+            .map_or((1., 0.), |zone| (zone.gravity, zone.drag))
+    }
+}
+
+#[derive(Component)]
+struct WindIndicator;
+
+pub struct EnvironmentPlugin;
+
+impl Plugin for EnvironmentPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Environment>()
+            .add_enter_system(AppState::InGame, setup_wind_indicator)
+            .add_exit_system(AppState::InGame, remove_wind_indicator)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::InGame)
+                    .run_unless_resource_exists::<Paused>()
+                    .with_system(environment_forces)
+                    .with_system(wind_indicator)
+                    .into(),
+            );
+    }
+}
+
+/// Applies the wind and the zones to every body they affect
+pub fn environment_forces(
+    time: Res<Time>,
+    rapier_config: Res<RapierConfiguration>,
+    mut env: ResMut<Environment>,
+    mut bodies: Query<(
+        &Transform,
+        &mut GravityScale,
+        &mut Damping,
+        &mut ExternalForce,
+        Option<&ReadMassProperties>,
+        Option<&WindBlown>,
+    )>,
+) {
+    // the headless simulation steps a fixed time per frame, whatever the clock says
+    env.time += match rapier_config.timestep_mode {
+        TimestepMode::Fixed { dt, .. } => dt,
+        TimestepMode::Variable { time_scale, .. }
+        | TimestepMode::Interpolated { time_scale, .. } => time.delta_seconds() * time_scale,
+    };
+    let wind = env.wind(0.);
+
+    for (trans, mut gravity_scale, mut damping, mut force, mass, blown) in bodies.iter_mut() {
+        let (gravity, drag) = env.zone_at(trans.translation.truncate());
+        if gravity_scale.0 != gravity {
+            gravity_scale.0 = gravity;
+        }
+        if damping.linear_damping != drag {
+            damping.linear_damping = drag;
+        }
+
+        let push = match (blown, mass) {
+            (Some(_), Some(mass)) => wind * mass.0.mass,
+            _ => Vec2::ZERO,
+        };
+        if force.force != push {
+            force.force = push;
+        }
+    }
+}
+
+fn setup_wind_indicator(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(5.),
+                    left: Val::Px(380.),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                    font_size: 30.0,
+                    color: Color::BLACK,
+                },
+            ),
+            ..default()
+        })
+        .insert(WindIndicator);
+}
+
+fn remove_wind_indicator(mut commands: Commands, query: Query<Entity, With<WindIndicator>>) {
+    for ent in query.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+}
+
+/// Shows which way the wind blows and how hard, in m/s²
+fn wind_indicator(env: Res<Environment>, mut query: Query<&mut Text, With<WindIndicator>>) {
+    let value = match env.wind {
+        Some(_) => {
+            let wind = env.wind(0.);
+            let arrows = ["→", "↗", "↑", "↖", "←", "↙", "↓", "↘"];
+            let octant = (wind.y.atan2(wind.x) / (TAU / 8.)).round().rem_euclid(8.) as usize;
+            format!(
+                "Wind {} {:.1}",
+                arrows[octant],
+                wind.length() / PIXELS_PER_METER
+            )
+        }
+        None => String::new(),
+    };
+
+    for mut text in query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
@@ -1,74 +1,341 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
 use bevy_prototype_lyon::prelude as lyon;
 use bevy_rapier2d::prelude::*;
+use futures_lite::future;
 use iyes_loopless::prelude::*;
 
 use super::AppState;
+use crate::actions::{Action, Actions};
+use crate::cli::Options;
 use crate::constants::{
-    GROUND_COLOR, GROUND_HEIGHT, LAUNCH_FACTOR, STONE1, STONE2, TARGET_COLOR, TARGET_FORCE_THRESH,
-    WOOD1, WOOD2,
+    Material, AIM_RETICLE_DISTANCE, BALL_REST_SPEED, BALL_REST_TIME, BLOCK_POINTS,
+    CAMERA_PAN_LIMIT, ENDLESS_LIVES, ENDLESS_SHOTS, ENDLESS_SHOT_DECAY, HEIGHT, IMPACT_SOUND_FORCE,
+    KEY_AIM_ANGLE_STEP, KEY_AIM_DEFAULT_POWER, KEY_AIM_POWER_STEP, KEY_AIM_REPEAT,
+    KEY_AIM_REPEAT_DELAY, LAUNCH_FACTOR, MAX_POWER, MOVER_COLOR, PIXELS_PER_METER, POWER_RATE,
+    ROPE_COLOR, SHOT_BONUS, SHOT_TIMEOUT, STONE1, STONE2, TARGET_COLOR, TARGET_FORCE_THRESH,
+    TARGET_POINTS, TRAJECTORY_DOTS, TRAJECTORY_STEP, UNDOS_PER_LEVEL, WIDTH, WIND_MAX_BLOCK_MASS,
+    WOOD1, WOOD2, ZONE_COLORS,
 };
-use crate::read_levels::read_levels;
+use crate::environment::{Environment, WindBlown};
+use crate::generator::{generate_level, level_seed};
+use crate::headless::settle_level;
+use crate::joints::{build_joint, rope_swivel, Rope};
+use crate::leaderboard::{campaign_table, start_name_entry, NameEntry};
+use crate::movers::Mover;
+use crate::read_levels::{read_levels_from, BodyRef, LevelData};
+use crate::replay::Replaying;
+use crate::settings::{AimMode, Settings};
+use crate::sound::{PlaySound, Sound};
+use crate::terrain::{spawn_ground, Terrain, WorldBounds};
 
 #[derive(PartialEq, Debug)]
-enum LevelState {
+pub enum LevelState {
     Prelaunch,
     Launched,
     Complete,
     LastLevelComplete,
+    Failed,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GameMode {
+    Campaign,
+    Endless,
+}
+
+/// Progress of a whole play session, from the main menu to game over
+#[derive(Clone)]
+pub struct Run {
+    pub mode: GameMode,
+    pub seed: u64,
+    pub score: u32,
+    pub lives: u32,
+    pub targets_destroyed: u32,
+    pub towers_cleared: u32,
 }
 
+impl Run {
+    /// A random seed for endless towers unless `seed` fixes it
+    pub fn new(mode: GameMode, seed: Option<u64>) -> Self {
+        Run {
+            mode,
+            seed: seed.unwrap_or_else(rand::random),
+            score: 0,
+            lives: ENDLESS_LIVES,
+            targets_destroyed: 0,
+            towers_cleared: 0,
+        }
+    }
+
+    /// The campaign gives one shot per level, endless mode starts generous
+    /// and takes shots away as the towers get harder
+    fn shots_for(&self, level: usize) -> usize {
+        match self.mode {
+            GameMode::Campaign => 1,
+            GameMode::Endless => ENDLESS_SHOTS
+                .saturating_sub(level / ENDLESS_SHOT_DECAY)
+                .max(1),
+        }
+    }
+}
+
+/// The camera the game aims, pans and draws with. `CameraPlugin` spawns
+/// one, an app with its own camera can tag that instead
+#[derive(Component)]
+pub struct GameCamera;
+
+#[derive(Component)]
+pub struct Ball;
+
+#[derive(Component)]
+pub struct Target;
+
+#[derive(Component)]
+pub struct Block;
+
 #[derive(Component)]
-struct Ball;
+pub struct BlockMaterial(pub &'static Material);
 
+/// Text shown for the whole level, kept when its bodies are rebuilt
 #[derive(Component)]
-struct Target;
+pub struct LevelText;
 
 #[derive(Component)]
-struct LevelText;
+struct HudText;
 
 #[derive(Component)]
 struct PowerIndicator;
 
+#[derive(Component)]
+struct TrajectoryDot;
+
+#[derive(Component)]
+struct AimReticle;
+
+#[derive(Component)]
+struct AimReadout;
+
+#[derive(Component)]
+struct PauseText;
+
 #[derive(Component)]
 struct GameNode;
 
+/// Removed with the level
 #[derive(Component)]
-struct LevelNode;
+pub struct LevelNode;
+
+/// Index of a block, or of a target after the blocks, in the level it was
+/// spawned from
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BodyId(pub usize);
+
+/// Index of a joint in the level it was spawned from
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct JointId(pub usize);
+
+/// The level being played, as it was loaded
+pub struct CurrentLevel(pub LevelData);
+
+/// The levels of the campaign. Loaded from `Options::levels` when the app
+/// doesn't insert them before adding `GamePlugin`
+pub struct Levels(pub Vec<LevelData>);
+
+/// Label of the system firing the ball, for systems that have to see a shot
+/// in the frame it is fired
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LaunchSystem;
+
+/// A level from a code, played on its own instead of the campaign until the
+/// game is left
+pub struct SharedLevel(pub LevelData);
+
+/// Levels settled by `prepare_level`, or being settled in the background, by
+/// index, so none is settled twice or holds up the game unless it's needed
+/// before it's ready
+#[derive(Default)]
+struct Prepared {
+    settled: HashMap<usize, LevelData>,
+    settling: HashMap<usize, Task<LevelData>>,
+}
+
+impl Prepared {
+    /// Starts preparing level `index` in the background, unless it already is
+    fn start(&mut self, index: usize, prepare: impl FnOnce() -> LevelData + Send + 'static) {
+        if self.settled.contains_key(&index) || self.settling.contains_key(&index) {
+            return;
+        }
+        let task = AsyncComputeTaskPool::get().spawn(async move { prepare() });
+        self.settling.insert(index, task);
+    }
+
+    /// Level `index` once prepared, waiting for it if it's still being
+    /// prepared, or preparing it here if it never was
+    fn get(&mut self, index: usize, prepare: impl FnOnce() -> LevelData) -> LevelData {
+        if let Some(task) = self.settling.remove(&index) {
+            self.settled.insert(index, future::block_on(task));
+        }
+        self.settled.entry(index).or_insert_with(prepare).clone()
+    }
+}
+
+/// The campaign, prepared from startup for as long as the app runs, and the
+/// levels of the game being played when they aren't the campaign's, a
+/// shared level or endless towers, dropped when it ends
+#[derive(Default)]
+struct PreparedLevels {
+    campaign: Prepared,
+    game: Prepared,
+}
 
 struct Power(f32);
 struct MaxLevel(usize);
 
-struct Game {
-    state: LevelState,
-    level: usize,
+/// Launch angle in degrees for keyboard aiming
+struct AimAngle(f32);
+
+/// Present while the game is paused
+pub struct Paused;
+
+/// The level being played
+pub struct Game {
+    pub state: LevelState,
+    pub level: usize,
+    pub shots_left: usize,
+    pub targets_left: usize,
+    pub undos_left: usize,
+    level_start_score: u32,
+    shot_time: f32,
+    rest_time: f32,
+}
+
+/// Sent to fire the ball towards `target`, a point in the world, with
+/// `power` up to `MAX_POWER`
+pub struct LaunchEvent {
+    pub power: f32,
+    pub target: Vec2,
+}
+
+/// A level was set up, on the first try or a restart
+pub struct LevelStarted {
+    pub level: usize,
+}
+
+/// The last shot ended with targets still standing
+pub struct LevelFailed {
+    pub level: usize,
+}
+
+/// Send to drop the level being played and start the given one instead
+pub struct LoadLevel(pub usize);
+
+/// The last target of a level is gone
+pub struct LevelCompleted {
+    pub level: usize,
+    /// Score of the whole run so far, shot bonus included
+    pub score: u32,
+}
+
+/// The ball was launched from `position`
+pub struct ShotFired {
+    pub position: Vec2,
+    pub impulse: Vec2,
+}
+
+/// The ball came to rest, left the world, ran out of time or the shot
+/// cleared the level
+pub struct ShotEnded {
+    pub shots_left: usize,
+}
+
+/// A target was hit hard enough or left the world
+pub struct TargetDestroyed {
+    pub position: Vec2,
 }
 
-struct LaunchEvent {
-    power: f32,
-    target: Vec2,
+/// A block was knocked out of the world
+pub struct BlockDestroyed {
+    pub position: Vec2,
 }
 
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<Levels>() {
+            let options = app
+                .world
+                .get_resource::<Options>()
+                .cloned()
+                .unwrap_or_default();
+            app.insert_resource(Levels(read_levels_from(&options.levels)));
+        }
+
         app.add_event::<LaunchEvent>()
+            .add_event::<ShotFired>()
+            .add_event::<ShotEnded>()
+            .add_event::<TargetDestroyed>()
+            .add_event::<BlockDestroyed>()
+            .add_event::<LevelStarted>()
+            .add_event::<LevelCompleted>()
+            .add_event::<LevelFailed>()
+            .add_event::<LoadLevel>()
             .insert_resource(Game {
                 state: LevelState::Prelaunch,
                 level: 0,
+                shots_left: 0,
+                targets_left: 0,
+                undos_left: 0,
+                level_start_score: 0,
+                shot_time: 0.,
+                rest_time: 0.,
             })
+            .insert_resource(Run::new(GameMode::Campaign, None))
             .insert_resource(Power(0.))
+            .insert_resource(AimAngle(45.))
+            .init_resource::<Terrain>()
+            .init_resource::<WorldBounds>()
+            .init_resource::<PreparedLevels>()
+            .add_startup_system(prepare_campaign)
             .add_enter_system(AppState::InGame, setup_game)
             .add_exit_system(AppState::InGame, remove_game)
             .add_system_set(
                 ConditionSet::new()
                     .run_in_state(AppState::InGame)
-                    .with_system(keyboard_listener)
+                    .with_system(
+                        toggle_pause
+                            .run_unless_resource_exists::<NameEntry>()
+                            .run_unless_resource_exists::<Replaying>(),
+                    )
+                    .with_system(camera_pan)
+                    .with_system(update_hud)
+                    .with_system(load_level)
+                    .into(),
+            )
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::InGame)
+                    .run_unless_resource_exists::<Paused>()
+                    .with_system(action_listener.run_unless_resource_exists::<NameEntry>())
+                    .with_system(keyboard_aim.run_if(is_prelaunch))
                     .with_system(power_indicator.run_if(is_prelaunch))
-                    .with_system(launch.run_if(is_prelaunch))
+                    .with_system(launch.run_if(is_prelaunch).label(LaunchSystem))
                     .with_system(target_collisions.run_if(is_launched))
+                    .with_system(score_lost_blocks.run_if(is_launched))
                     .with_system(level_complete.run_if(is_launched))
+                    .with_system(shot_ended.run_if(is_launched))
+                    .into(),
+            )
+            .add_system_set_to_stage(
+                CoreStage::PostUpdate,
+                ConditionSet::new()
+                    .run_in_state(AppState::InGame)
+                    .run_unless_resource_exists::<Paused>()
+                    .run_if(is_launched)
+                    .with_system(out_of_bounds)
                     .into(),
             );
     }
@@ -84,82 +351,211 @@ fn is_launched(game: Res<Game>) -> bool {
 
 fn setup_game(
     mut commands: Commands,
-    mut windows: ResMut<Windows>,
-    game: ResMut<Game>,
+    mut game: ResMut<Game>,
+    run: Res<Run>,
+    settings: Res<Settings>,
     asset_server: Res<AssetServer>,
+    mut options: ResMut<Options>,
+    levels: Res<Levels>,
+    shared: Option<Res<SharedLevel>>,
+    mut prepared: ResMut<PreparedLevels>,
+    started: EventWriter<LevelStarted>,
 ) {
-    let window = windows.get_primary_mut().unwrap();
-    let (win_w, win_h) = (window.width(), window.height());
+    let max_level = campaign_levels(&levels, shared.as_deref()).len() - 1;
+    commands.insert_resource(MaxLevel(max_level));
 
-    let level_data = read_levels();
-    commands.insert_resource(MaxLevel(level_data.len() - 1));
+    game.level = options.level.take().unwrap_or(0).min(max_level);
 
-    // ground
     commands
-        .spawn()
-        .insert_bundle(lyon::GeometryBuilder::build_as(
-            &lyon::shapes::Rectangle {
-                extents: Vec2::new(win_w, GROUND_HEIGHT * 2.),
-                origin: lyon::shapes::RectangleOrigin::Center,
-            },
-            lyon::DrawMode::Outlined {
-                fill_mode: lyon::FillMode::color(GROUND_COLOR),
-                outline_mode: lyon::StrokeMode::color(GROUND_COLOR),
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(35.),
+                    left: Val::Px(8.),
+                    ..default()
+                },
+                ..default()
             },
-            Transform::default(),
-        ))
-        .insert(RigidBody::Fixed)
-        .insert(Collider::cuboid(win_w / 2., GROUND_HEIGHT))
-        .insert(Transform::from_xyz(0.0, -win_h / 2., 0.0))
-        .insert(GameNode);
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                    font_size: 24.0,
+                    color: Color::BLACK,
+                },
+            ),
+            ..default()
+        })
+        .insert(GameNode)
+        .insert(HudText);
 
     commands
         .spawn_bundle(TextBundle {
             style: Style {
                 position_type: PositionType::Absolute,
                 position: UiRect {
-                    top: Val::Px(5.),
-                    right: Val::Px(15.),
+                    bottom: Val::Px(40.),
+                    left: Val::Px(8.),
                     ..default()
                 },
                 ..default()
             },
-            text: Text {
-                sections: vec![TextSection {
-                    value: "Press \"r\" to restart".to_string(),
-                    style: TextStyle {
-                        font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
-                        font_size: 30.0,
-                        color: Color::BLACK,
-                    },
-                }],
-                ..default()
-            },
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                    font_size: 24.0,
+                    color: Color::BLACK,
+                },
+            ),
             ..default()
         })
-        .insert(GameNode);
+        .insert(GameNode)
+        .insert(AimReadout);
 
-    setup_level(commands, windows, game, asset_server);
+    // there is no second try at a tower in endless mode
+    if run.mode == GameMode::Campaign {
+        commands
+            .spawn_bundle(TextBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(5.),
+                        right: Val::Px(15.),
+                        ..default()
+                    },
+                    ..default()
+                },
+                text: Text {
+                    sections: vec![TextSection {
+                        value: format!(
+                            "Press \"{}\" to restart",
+                            settings.binding(Action::Restart)
+                        ),
+                        style: TextStyle {
+                            font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                            font_size: 30.0,
+                            color: Color::BLACK,
+                        },
+                    }],
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(GameNode);
+    }
+
+    setup_level(
+        commands,
+        game,
+        run,
+        settings,
+        asset_server,
+        &levels,
+        shared.as_deref(),
+        &mut prepared,
+        started,
+    );
+}
+
+/// The levels of the campaign, or only the shared one while playing a code
+fn campaign_levels<'a>(levels: &'a Levels, shared: Option<&'a SharedLevel>) -> &'a [LevelData] {
+    match shared {
+        Some(shared) => std::slice::from_ref(&shared.0),
+        None => &levels.0,
+    }
+}
+
+/// Starts settling every level of the campaign in the background
+fn prepare_campaign(levels: Res<Levels>, mut prepared: ResMut<PreparedLevels>) {
+    let last = levels.0.len().saturating_sub(1);
+    for (i, level) in levels.0.iter().enumerate() {
+        let level = level.clone();
+        prepared.campaign.start(i, move || {
+            prepare_level(&level, &format!("Level {} / {}", i, last))
+        });
+    }
+}
+
+/// Settles a level before it is played, warning about towers that can't
+/// stand as written
+fn prepare_level(level: &LevelData, label: &str) -> LevelData {
+    let settled = settle_level(level);
+    if !settled.rested {
+        warn!("{}: the tower is still moving before the first shot", label);
+    }
+    if settled.collapsed {
+        warn!("{}: the tower collapses before the first shot", label);
+    }
+    if settled.targets_lost > 0 {
+        warn!(
+            "{}: {} target(s) destroyed before the first shot",
+            label, settled.targets_lost
+        );
+    }
+    settled.level
 }
 
 fn setup_level(
     mut commands: Commands,
-    mut windows: ResMut<Windows>,
     mut game: ResMut<Game>,
+    run: Res<Run>,
+    settings: Res<Settings>,
     asset_server: Res<AssetServer>,
+    levels: &Levels,
+    shared: Option<&SharedLevel>,
+    prepared: &mut PreparedLevels,
+    mut started: EventWriter<LevelStarted>,
 ) {
-    let level_data = read_levels();
+    let (level, level_label) = match run.mode {
+        GameMode::Campaign => {
+            let levels = campaign_levels(levels, shared);
+            let label = match shared {
+                Some(_) => "Shared level".to_string(),
+                None => format!("Level {} / {}", game.level, levels.len() - 1),
+            };
+            let prepared = match shared {
+                Some(_) => &mut prepared.game,
+                None => &mut prepared.campaign,
+            };
+            let level = prepared.get(game.level, || prepare_level(&levels[game.level], &label));
+            (level, label)
+        }
+        GameMode::Endless => {
+            let label = format!("Tower {}", game.level + 1);
+            let level = prepared.game.get(game.level, || {
+                let seed = level_seed(run.seed, game.level);
+                prepare_level(&generate_level(seed, game.level), &label)
+            });
 
-    let window = windows.get_primary_mut().unwrap();
-    let (win_w, win_h) = (window.width(), window.height());
+            // build the following tower while this one is played
+            let next = game.level + 1;
+            let seed = level_seed(run.seed, next);
+            prepared.game.start(next, move || {
+                let label = format!("Tower {}", next + 1);
+                prepare_level(&generate_level(seed, next), &label)
+            });
+
+            (level, label)
+        }
+    };
+
+    let (win_w, win_h) = (WIDTH, HEIGHT);
 
     game.state = LevelState::Prelaunch;
+    game.shots_left = run.shots_for(game.level);
+    // taking back a shot would give back an endless life, and with it a
+    // place on the high scores
+    game.undos_left = match run.mode {
+        GameMode::Campaign => UNDOS_PER_LEVEL,
+        GameMode::Endless => 0,
+    };
+    game.targets_left = level.targets.len();
+    game.level_start_score = run.score;
+    game.shot_time = 0.;
+    game.rest_time = 0.;
 
-    let level_label = format!(
-        "Level {} / {}",
-        game.level.to_string(),
-        level_data.len() - 1
-    );
     commands
         .spawn_bundle(TextBundle {
             style: Style {
@@ -184,33 +580,6 @@ fn setup_level(
         .insert(LevelNode)
         .insert(LevelText);
 
-    // ball
-    commands
-        .spawn()
-        .insert_bundle(lyon::GeometryBuilder::build_as(
-            &lyon::shapes::Circle {
-                radius: 10.,
-                center: Vec2::ZERO,
-            },
-            lyon::DrawMode::Outlined {
-                fill_mode: lyon::FillMode::color(Color::BLACK),
-                outline_mode: lyon::StrokeMode::color(Color::BLACK),
-            },
-            Transform::default(),
-        ))
-        .insert(RigidBody::Dynamic)
-        .insert(Collider::ball(10.))
-        .insert(Restitution::coefficient(0.7))
-        .insert(ExternalImpulse::default())
-        .insert(ColliderMassProperties::Density(1.0))
-        .insert(Transform::from_xyz(
-            -win_w / 4.,
-            (-win_h / 2.0) + (30. / 2.) + GROUND_HEIGHT,
-            0.0,
-        ))
-        .insert(LevelNode)
-        .insert(Ball);
-
     // help text for first level
     if game.level == 0 {
         commands
@@ -218,15 +587,17 @@ fn setup_level(
                 style: Style {
                     position_type: PositionType::Absolute,
                     position: UiRect {
-                        top: Val::Px(50.),
+                        top: Val::Px(65.),
                         left: Val::Px(8.),
                         ..default()
                     },
                     ..default()
                 },
                 text: Text::from_section(
-                    "1. Aim with mouse\n2. Click, hold, and release \nRight Mouse Button to launch"
-                        .to_string(),
+                    format!(
+                        "1. Aim with mouse or left stick\n2. Hold and release \"{}\"\nor right trigger to launch",
+                        settings.binding(Action::Fire)
+                    ),
                     TextStyle {
                         font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
                         font_size: 30.0,
@@ -239,18 +610,128 @@ fn setup_level(
             .insert(LevelText);
     }
 
-    // spawn the tower
-    for node in level_data[game.level].tower.iter() {
-        let mat = match node.kind.as_str() {
-            "wood1" => WOOD1,
-            "wood2" => WOOD2,
-            "stone1" => STONE1,
-            "stone2" => STONE2,
-            _ => WOOD1,
-        };
+    spawn_level_bodies(&mut commands, &level, win_w, win_h);
+    commands.insert_resource(CurrentLevel(level));
+    started.send(LevelStarted { level: game.level });
+}
 
+/// Spawns a target resting at `pos` in the world, removed with the level
+pub fn spawn_target(commands: &mut Commands, pos: Vec2) -> Entity {
+    commands
+        .spawn()
+        .insert_bundle(lyon::GeometryBuilder::build_as(
+            &lyon::shapes::Circle {
+                radius: 10.,
+                center: Vec2::ZERO,
+            },
+            lyon::DrawMode::Outlined {
+                fill_mode: lyon::FillMode::color(TARGET_COLOR.0),
+                outline_mode: lyon::StrokeMode::color(TARGET_COLOR.1),
+            },
+            Transform::default(),
+        ))
+        .insert(RigidBody::Dynamic)
+        .insert(Collider::ball(10.))
+        .insert(Restitution::coefficient(0.7))
+        .insert(ActiveEvents::default())
+        .insert(ContactForceEventThreshold(TARGET_FORCE_THRESH))
+        .insert(ExternalForce::default())
+        .insert(GravityScale::default())
+        .insert(Damping::default())
+        .insert(Velocity::default())
+        .insert(Transform::from_translation(pos.extend(0.0)))
+        .insert(LevelNode)
+        .insert(Target)
+        .id()
+}
+
+/// Impulse applied to the ball when launched from `from` towards `target`
+pub fn launch_impulse(from: Vec2, target: Vec2, power: f32) -> Vec2 {
+    // aiming at the ball itself launches nothing rather than NaN
+    (target - from).normalize_or_zero() * power * LAUNCH_FACTOR
+}
+
+/// Spawns the physics bodies of a level: the ball, the tower, the targets,
+/// the moving parts and the joints holding them together
+pub fn spawn_level_bodies(commands: &mut Commands, level: &LevelData, win_w: f32, win_h: f32) {
+    let terrain = Terrain::new(level, win_w, win_h);
+    let origin = terrain.origin();
+    let ground = spawn_ground(commands, &terrain);
+    commands.entity(ground).insert(LevelNode);
+
+    let env = Environment::new(level, origin);
+
+    // zones are drawn behind everything
+    for zone in env.zones.iter() {
+        let color = if zone.gravity < 1. {
+            ZONE_COLORS[0]
+        } else if zone.gravity > 1. {
+            ZONE_COLORS[1]
+        } else {
+            ZONE_COLORS[2]
+        };
         commands
             .spawn()
+            .insert_bundle(lyon::GeometryBuilder::build_as(
+                &lyon::shapes::Rectangle {
+                    extents: zone.max - zone.min,
+                    origin: lyon::shapes::RectangleOrigin::BottomLeft,
+                },
+                lyon::DrawMode::Fill(lyon::FillMode::color(color)),
+                Transform::default(),
+            ))
+            .insert(Transform::from_translation(zone.min.extend(-1.0)))
+            .insert(LevelNode);
+    }
+    commands.insert_resource(env);
+
+    // ball
+    commands
+        .spawn()
+        .insert_bundle(lyon::GeometryBuilder::build_as(
+            &lyon::shapes::Circle {
+                radius: 10.,
+                center: Vec2::ZERO,
+            },
+            lyon::DrawMode::Outlined {
+                fill_mode: lyon::FillMode::color(Color::BLACK),
+                outline_mode: lyon::StrokeMode::color(Color::BLACK),
+            },
+            Transform::default(),
+        ))
+        .insert(RigidBody::Dynamic)
+        .insert(Collider::ball(10.))
+        .insert(Restitution::coefficient(0.7))
+        .insert(ActiveEvents::default())
+        .insert(ContactForceEventThreshold(IMPACT_SOUND_FORCE))
+        .insert(ExternalImpulse::default())
+        .insert(ExternalForce::default())
+        .insert(GravityScale::default())
+        .insert(Damping::default())
+        .insert(ColliderMassProperties::Density(1.0))
+        .insert(ReadMassProperties::default())
+        .insert(Velocity::default())
+        .insert(Transform::from_translation(
+            terrain.ball_start().extend(0.0),
+        ))
+        .insert(LevelNode)
+        .insert(Ball);
+
+    // spawn the tower
+    let mut blocks = Vec::new();
+    for (i, node) in level.tower.iter().enumerate() {
+        let mat: &'static Material = match node.kind.as_str() {
+            "wood1" => &WOOD1,
+            "wood2" => &WOOD2,
+            "stone1" => &STONE1,
+            "stone2" => &STONE2,
+            _ => &WOOD1,
+        };
+
+        let pos = origin + Vec2::new(node.x + (node.w / 2.), node.y + (node.h / 2.));
+        let mut block = commands.spawn();
+        blocks.push((block.id(), pos, node.rotation.to_radians()));
+        block
             .insert_bundle(lyon::GeometryBuilder::build_as(
                 &lyon::shapes::Rectangle {
                     extents: Vec2::new(node.w, node.h),
@@ -265,49 +746,194 @@ fn setup_level(
             .insert(RigidBody::Dynamic)
             .insert(Collider::cuboid(node.w / 2., node.h / 2.))
             .insert(ColliderMassProperties::Density(mat.density))
-            .insert(Transform::from_xyz(
-                (win_w / 4.) + node.x + (node.w / 2.),
-                (-win_h / 2.) + node.y + (node.h / 2.) + GROUND_HEIGHT,
-                5.0,
-            ))
-            .insert(LevelNode);
+            .insert(ActiveEvents::default())
+            .insert(ContactForceEventThreshold(IMPACT_SOUND_FORCE))
+            .insert(ExternalForce::default())
+            .insert(GravityScale::default())
+            .insert(Damping::default())
+            .insert(Velocity::default())
+            .insert(
+                Transform::from_translation(pos.extend(5.0))
+                    .with_rotation(Quat::from_rotation_z(node.rotation.to_radians())),
+            )
+            .insert(LevelNode)
+            .insert(BodyId(i))
+            .insert(Block)
+            .insert(BlockMaterial(mat));
+
+        // Rapier measures the area in meters, not pixels
+        let mass = mat.density * node.w * node.h / PIXELS_PER_METER.powi(2);
+        if mass <= WIND_MAX_BLOCK_MASS {
+            block
+                .insert(WindBlown)
+                .insert(ReadMassProperties::default());
+        }
     }
 
     // spawn the targets
-    for node in level_data[game.level].targets.iter() {
+    let mut targets = Vec::new();
+    for (i, node) in level.targets.iter().enumerate() {
+        let pos = origin + Vec2::new(node.x, node.y + 5.);
+        let target = spawn_target(commands, pos);
         commands
-            .spawn()
-            .insert_bundle(lyon::GeometryBuilder::build_as(
-                &lyon::shapes::Circle {
-                    radius: 10.,
-                    center: Vec2::ZERO,
-                },
-                lyon::DrawMode::Outlined {
-                    fill_mode: lyon::FillMode::color(TARGET_COLOR.0),
-                    outline_mode: lyon::StrokeMode::color(TARGET_COLOR.1),
-                },
-                Transform::default(),
-            ))
-            .insert(RigidBody::Dynamic)
-            .insert(Collider::ball(10.))
-            .insert(Restitution::coefficient(0.7))
-            .insert(ActiveEvents::default())
-            .insert(ContactForceEventThreshold(TARGET_FORCE_THRESH))
-            .insert(Transform::from_xyz(
-                (win_w / 4.) + node.x,
-                (-win_h / 2.0) + node.y + 5. + GROUND_HEIGHT,
-                0.0,
-            ))
-            .insert(LevelNode)
-            .insert(Target);
+            .entity(target)
+            .insert(BodyId(level.tower.len() + i));
+        targets.push((target, pos, 0.));
+
+        if let Some(path) = &node.path {
+            commands
+                .entity(target)
+                .insert(RigidBody::KinematicPositionBased)
+                .insert(Mover::new(pos, Some(path), 0., None));
+        }
+    }
+
+    // spawn the moving parts
+    for node in level.movers.iter() {
+        let mover = Mover::new(
+            origin + Vec2::new(node.x, node.y),
+            node.path.as_ref(),
+            node.angular_speed,
+            node.pendulum.clone(),
+        );
+        let (pos, angle) = mover.pose(0.);
+
+        let mut ent = commands.spawn();
+        ent.insert_bundle(lyon::GeometryBuilder::build_as(
+            &lyon::shapes::Rectangle {
+                extents: Vec2::new(node.w, node.h),
+                origin: lyon::shapes::RectangleOrigin::Center,
+            },
+            lyon::DrawMode::Outlined {
+                fill_mode: lyon::FillMode::color(MOVER_COLOR.0),
+                outline_mode: lyon::StrokeMode::new(MOVER_COLOR.1, 2.),
+            },
+            Transform::default(),
+        ))
+        .insert(RigidBody::KinematicPositionBased)
+        .insert(Collider::cuboid(node.w / 2., node.h / 2.))
+        .insert(
+            Transform::from_translation(pos.extend(5.0))
+                .with_rotation(Quat::from_rotation_z(angle)),
+        )
+        .insert(LevelNode)
+        .insert(mover);
+
+        // the rod of a pendulum, up to the pivot
+        if let Some(pendulum) = &node.pendulum {
+            ent.with_children(|parent| {
+                parent.spawn_bundle(lyon::GeometryBuilder::build_as(
+                    &lyon::shapes::Line(Vec2::ZERO, Vec2::new(0., pendulum.length)),
+                    lyon::DrawMode::Stroke(lyon::StrokeMode::new(MOVER_COLOR.1, 3.)),
+                    Transform::from_xyz(0., 0., -0.1),
+                ));
+            });
+        }
     }
+
+    // tie the bodies together
+    let find = |body: BodyRef| match body {
+        BodyRef::Tower(i) => blocks.get(i).copied(),
+        BodyRef::Target(i) => targets.get(i).copied(),
+    };
+    for (i, joint) in level.joints.iter().enumerate() {
+        let anchor = origin + Vec2::new(joint.x, joint.y);
+        let (a, a_pos, a_angle) = match find(joint.a) {
+            Some(a) => a,
+            None => {
+                warn!("joint on missing body {:?}", joint.a);
+                continue;
+            }
+        };
+        let (b, b_pos, b_angle) = match joint.b {
+            Some(b) => match find(b) {
+                Some(b) => b,
+                None => {
+                    warn!("joint on missing body {:?}", b);
+                    continue;
+                }
+            },
+            // pinned to the world with a body that never moves
+            None => {
+                let pin = commands
+                    .spawn_bundle(TransformBundle::from(Transform::from_translation(
+                        anchor.extend(0.0),
+                    )))
+                    .insert(RigidBody::Fixed)
+                    .insert(LevelNode)
+                    .id();
+                (pin, anchor, 0.)
+            }
+        };
+
+        // where the anchor is on each body, turned with it
+        let local = |pos: Vec2, angle: f32| {
+            (Quat::from_rotation_z(-angle) * (anchor - pos).extend(0.)).truncate()
+        };
+
+        // a body can only hold one joint, so each gets its own child entity
+        let (data, glue) = build_joint(
+            joint,
+            local(a_pos, a_angle),
+            local(b_pos, b_angle),
+            a_angle,
+            b_angle,
+        );
+        let holder = match joint.kind.as_str() {
+            "rope" => commands
+                .spawn_bundle(TransformBundle::from(Transform::from_translation(
+                    anchor.extend(0.0),
+                )))
+                .insert_bundle(rope_swivel(b, local(b_pos, b_angle)))
+                .insert(LevelNode)
+                .id(),
+            _ => b,
+        };
+        commands.entity(a).with_children(|parent| {
+            let mut ent = parent.spawn();
+            ent.insert(ImpulseJoint::new(holder, data))
+                .insert(JointId(i));
+            if let Some(glue) = glue {
+                ent.insert(glue);
+            }
+        });
+
+        if joint.kind == "rope" {
+            commands
+                .spawn_bundle(lyon::GeometryBuilder::build_as(
+                    &lyon::shapes::Line(a_pos, anchor),
+                    lyon::DrawMode::Stroke(lyon::StrokeMode::new(ROPE_COLOR, 2.)),
+                    Transform::from_xyz(0., 0., 4.0),
+                ))
+                .insert(Rope {
+                    a,
+                    b,
+                    anchor: local(b_pos, b_angle),
+                })
+                .insert(LevelNode);
+        }
+    }
+
+    commands.insert_resource(WorldBounds::new(level, origin, win_w, win_h));
+    commands.insert_resource(terrain);
 }
 
 fn remove_game(
     mut commands: Commands,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut cameras: Query<&mut Transform, With<GameCamera>>,
     level_nodes: Query<Entity, With<LevelNode>>,
     game_nodes: Query<Entity, With<GameNode>>,
+    mut prepared: ResMut<PreparedLevels>,
 ) {
+    commands.remove_resource::<Paused>();
+    commands.remove_resource::<SharedLevel>();
+    prepared.game = Prepared::default();
+    rapier_config.physics_pipeline_active = true;
+    // the console may have changed it
+    rapier_config.gravity = RapierConfiguration::default().gravity;
+    reset_camera(&mut cameras);
+
     for ent in level_nodes.iter() {
         commands.entity(ent).despawn_recursive();
     }
@@ -317,54 +943,400 @@ fn remove_game(
     }
 }
 
-fn keyboard_listener(
+fn action_listener(
     mut commands: Commands,
-    windows: ResMut<Windows>,
     mut game: ResMut<Game>,
+    run: Res<Run>,
+    settings: Res<Settings>,
     asset_server: Res<AssetServer>,
-    keyboard: Res<Input<KeyCode>>,
+    actions: Res<Actions>,
+    mut cameras: Query<&mut Transform, With<GameCamera>>,
     level_nodes: Query<Entity, With<LevelNode>>,
+    levels: Res<Levels>,
+    shared: Option<Res<SharedLevel>>,
+    mut prepared: ResMut<PreparedLevels>,
+    started: EventWriter<LevelStarted>,
 ) {
-    if keyboard.just_pressed(KeyCode::R) {
+    let can_advance = game.state == LevelState::Complete
+        || (game.state == LevelState::Failed && run.mode == GameMode::Endless);
+
+    if actions.just_pressed(Action::Restart) && run.mode == GameMode::Campaign {
         for ent in level_nodes.iter() {
-            commands.entity(ent).despawn();
+            commands.entity(ent).despawn_recursive();
         }
 
-        setup_level(commands, windows, game, asset_server);
-    } else if keyboard.just_pressed(KeyCode::N) && game.state == LevelState::Complete {
+        reset_camera(&mut cameras);
+        setup_level(
+            commands,
+            game,
+            run,
+            settings,
+            asset_server,
+            &levels,
+            shared.as_deref(),
+            &mut prepared,
+            started,
+        );
+    } else if actions.just_pressed(Action::NextLevel) && can_advance {
         for ent in level_nodes.iter() {
-            commands.entity(ent).despawn();
+            commands.entity(ent).despawn_recursive();
         }
 
         game.level += 1;
-        setup_level(commands, windows, game, asset_server);
-    } else if keyboard.just_pressed(KeyCode::Q) && game.state == LevelState::LastLevelComplete {
+        reset_camera(&mut cameras);
+        setup_level(
+            commands,
+            game,
+            run,
+            settings,
+            asset_server,
+            &levels,
+            shared.as_deref(),
+            &mut prepared,
+            started,
+        );
+    } else if actions.just_pressed(Action::Quit) && game.state == LevelState::LastLevelComplete {
         commands.insert_resource(NextState(AppState::MainMenu));
     }
 }
 
+fn load_level(
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    run: Res<Run>,
+    settings: Res<Settings>,
+    asset_server: Res<AssetServer>,
+    max_level: Res<MaxLevel>,
+    mut cameras: Query<&mut Transform, With<GameCamera>>,
+    level_nodes: Query<Entity, With<LevelNode>>,
+    levels: Res<Levels>,
+    shared: Option<Res<SharedLevel>>,
+    mut prepared: ResMut<PreparedLevels>,
+    mut load_events: EventReader<LoadLevel>,
+    started: EventWriter<LevelStarted>,
+) {
+    let level = match load_events.iter().last() {
+        Some(LoadLevel(level)) => *level,
+        None => return,
+    };
+
+    for ent in level_nodes.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+
+    game.level = match run.mode {
+        GameMode::Campaign => level.min(max_level.0),
+        GameMode::Endless => level,
+    };
+    reset_camera(&mut cameras);
+    setup_level(
+        commands,
+        game,
+        run,
+        settings,
+        asset_server,
+        &levels,
+        shared.as_deref(),
+        &mut prepared,
+        started,
+    );
+}
+
+/// Freezes the physics and every gameplay system until pressed again
+fn toggle_pause(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    paused: Option<Res<Paused>>,
+    asset_server: Res<AssetServer>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    pause_text: Query<Entity, With<PauseText>>,
+) {
+    if !actions.just_pressed(Action::Pause) {
+        return;
+    }
+
+    if paused.is_some() {
+        commands.remove_resource::<Paused>();
+        rapier_config.physics_pipeline_active = true;
+        for ent in pause_text.iter() {
+            commands.entity(ent).despawn();
+        }
+    } else {
+        commands.insert_resource(Paused);
+        rapier_config.physics_pipeline_active = false;
+        commands
+            .spawn_bundle(TextBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(250.),
+                        left: Val::Px(360.),
+                        ..default()
+                    },
+                    ..default()
+                },
+                text: Text::from_section(
+                    "Paused",
+                    TextStyle {
+                        font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                        font_size: 50.0,
+                        color: Color::BLACK,
+                    },
+                ),
+                ..default()
+            })
+            .insert(GameNode)
+            .insert(PauseText);
+    }
+}
+
+fn reset_camera(cameras: &mut Query<&mut Transform, With<GameCamera>>) {
+    for mut trans in cameras.iter_mut() {
+        trans.translation.x = 0.;
+        trans.translation.y = 0.;
+    }
+}
+
+/// Drags the view while the pan action is held, keeping the point first
+/// grabbed under the cursor
+fn camera_pan(
+    actions: Res<Actions>,
+    windows: Res<Windows>,
+    mut cameras: Query<(&Camera, &GlobalTransform, &mut Transform), With<GameCamera>>,
+    mut grabbed: Local<Option<Vec2>>,
+) {
+    if !actions.pressed(Action::CameraPan) {
+        *grabbed = None;
+        return;
+    }
+
+    let (window, (camera, camera_global, mut camera_trans)) =
+        match (windows.get_primary(), cameras.get_single_mut()) {
+            (Some(window), Ok(camera)) => (window, camera),
+            _ => return,
+        };
+    let cursor = match cursor_world_position(window, camera, camera_global) {
+        Some(cursor) => cursor,
+        None => return,
+    };
+
+    match *grabbed {
+        None => *grabbed = Some(cursor),
+        Some(point) => {
+            let pos = (camera_trans.translation.truncate() + point - cursor)
+                .clamp(-CAMERA_PAN_LIMIT, CAMERA_PAN_LIMIT);
+            camera_trans.translation = pos.extend(camera_trans.translation.z);
+        }
+    }
+}
+
+/// Converts the cursor position to world coordinates, taking the camera's
+/// scaling into account. `None` when the cursor is outside the window
+pub fn cursor_world_position(
+    window: &Window,
+    camera: &Camera,
+    camera_trans: &GlobalTransform,
+) -> Option<Vec2> {
+    let cursor = window.cursor_position()?;
+    let size = Vec2::new(window.width(), window.height());
+    let ndc = (cursor / size) * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_trans.compute_matrix() * camera.projection_matrix().inverse();
+    Some(ndc_to_world.project_point3(ndc.extend(-1.0)).truncate())
+}
+
+/// Points along the ball's flight, ignoring collisions. Stepped the way the
+/// physics would so the wind and zones bend the path the same way
+pub fn predict_trajectory(
+    start: Vec2,
+    velocity: Vec2,
+    gravity: Vec2,
+    env: &Environment,
+) -> Vec<Vec2> {
+    const SUBSTEPS: usize = 6;
+    let dt = TRAJECTORY_STEP / SUBSTEPS as f32;
+
+    let (mut pos, mut vel) = (start, velocity);
+    let mut t = 0.;
+    (1..=TRAJECTORY_DOTS)
+        .map(|_| {
+            for _ in 0..SUBSTEPS {
+                let (gravity_scale, drag) = env.zone_at(pos);
+                vel += (gravity * gravity_scale + env.wind(t)) * dt;
+                vel /= 1. + dt * drag;
+                pos += vel * dt;
+                t += dt;
+            }
+            pos
+        })
+        .collect()
+}
+
+/// Arrow key aiming. A tap nudges the angle or power by one step, holding
+/// the key keeps stepping after a short delay
+fn keyboard_aim(
+    time: Res<Time>,
+    actions: Res<Actions>,
+    settings: Res<Settings>,
+    mut angle: ResMut<AimAngle>,
+    mut power: ResMut<Power>,
+    mut held: Local<f32>,
+) {
+    if settings.aim_mode != AimMode::Keyboard {
+        return;
+    }
+
+    let axis = |up, down| actions.pressed(up) as i32 - actions.pressed(down) as i32;
+    let angle_dir = axis(Action::AngleUp, Action::AngleDown);
+    let power_dir = axis(Action::PowerUp, Action::PowerDown);
+    if angle_dir == 0 && power_dir == 0 {
+        return;
+    }
+
+    let tapped = [
+        Action::AngleUp,
+        Action::AngleDown,
+        Action::PowerUp,
+        Action::PowerDown,
+    ]
+    .into_iter()
+    .any(|action| actions.just_pressed(action));
+
+    let mut steps = 0;
+    if tapped {
+        *held = 0.;
+        steps = 1;
+    } else {
+        *held += time.delta_seconds();
+        while *held >= KEY_AIM_REPEAT_DELAY {
+            *held -= KEY_AIM_REPEAT;
+            steps += 1;
+        }
+    }
+
+    angle.0 = (angle.0 + (angle_dir * steps) as f32 * KEY_AIM_ANGLE_STEP).clamp(-90., 90.);
+    power.0 = (power.0 + (power_dir * steps) as f32 * KEY_AIM_POWER_STEP)
+        .clamp(KEY_AIM_POWER_STEP, MAX_POWER);
+}
+
 fn power_indicator(
     mut commands: Commands,
-    mouse: Res<Input<MouseButton>>,
+    actions: Res<Actions>,
     windows: Res<Windows>,
+    settings: Res<Settings>,
+    rapier_config: Res<RapierConfiguration>,
+    env: Res<Environment>,
+    angle: Res<AimAngle>,
+    cameras: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    ball: Query<(&Transform, &ReadMassProperties), With<Ball>>,
     mut power: ResMut<Power>,
     mut launch_evt: EventWriter<LaunchEvent>,
-    mut query: Query<Entity, With<PowerIndicator>>,
+    query: Query<Entity, Or<(With<PowerIndicator>, With<TrajectoryDot>, With<AimReticle>)>>,
+    mut readout: Query<&mut Text, With<AimReadout>>,
+    mut last_power: Local<f32>,
+    mut locked: Local<bool>,
 ) {
-    if mouse.pressed(MouseButton::Right) {
-        let window = windows.get_primary().unwrap();
-        let pos1 = window.cursor_position().unwrap();
-        let pos2 = pos1 - Vec2::new(window.width() / 2., window.height() / 2.);
+    for ent in query.iter() {
+        commands.entity(ent).despawn();
+    }
 
-        if let Ok(ent) = query.get_single_mut() {
-            commands.entity(ent).despawn();
+    let (ball_trans, mass) = match ball.get_single() {
+        Ok(ball) => ball,
+        Err(_) => return,
+    };
+    let ball_pos = ball_trans.translation.truncate();
+
+    // a gamepad or the keyboard aims with a virtual reticle around the ball
+    let keyboard = settings.aim_mode == AimMode::Keyboard;
+    if keyboard && power.0 <= 0. {
+        power.0 = KEY_AIM_DEFAULT_POWER;
+    }
+    let (window, (camera, camera_trans)) = match (windows.get_primary(), cameras.get_single()) {
+        (Some(window), Ok(camera)) => (window, camera),
+        _ => return,
+    };
+    let reticle = match actions.stick_aim {
+        _ if keyboard => {
+            let rad = angle.0.to_radians();
+            Some(Vec2::new(rad.cos(), rad.sin()))
+        }
+        stick => stick,
+    };
+    let cursor = match reticle {
+        Some(dir) => ball_pos + dir * AIM_RETICLE_DISTANCE,
+        None => match cursor_world_position(window, camera, camera_trans) {
+            Some(cursor) => cursor,
+            // releasing outside the window cancels the shot
+            None => {
+                power.0 = 0.;
+                return;
+            }
+        },
+    };
+
+    if reticle.is_some() {
+        commands
+            .spawn()
+            .insert_bundle(lyon::GeometryBuilder::build_as(
+                &lyon::shapes::Circle {
+                    radius: 8.,
+                    center: Vec2::ZERO,
+                },
+                lyon::DrawMode::Stroke(lyon::StrokeMode::new(Color::rgba(0.1, 0.1, 0.1, 0.8), 2.)),
+                Transform::default(),
+            ))
+            .insert(Transform::from_xyz(cursor.x, cursor.y, 11.0))
+            .insert(AimReticle);
+    }
+
+    // the keyboard sets the power directly, a locked charge keeps going
+    // until fire is pressed again
+    let (charging, firing) = if keyboard {
+        (false, actions.just_pressed(Action::Fire))
+    } else if settings.lock_charge {
+        if actions.just_pressed(Action::Fire) {
+            *locked = !*locked;
+            (*locked, !*locked)
+        } else {
+            (*locked, false)
         }
+    } else {
+        (
+            actions.pressed(Action::Fire),
+            actions.just_released(Action::Fire),
+        )
+    };
+
+    let target = match settings.aim_mode {
+        AimMode::Cursor | AimMode::Keyboard => cursor,
+        AimMode::Slingshot => 2. * ball_pos - cursor,
+    };
 
+    // aiming without charging previews the previous shot's power
+    let shown_power = if charging || keyboard {
+        power.0
+    } else {
+        *last_power
+    };
+
+    if !charging && !firing && !keyboard && !actions.pressed(Action::Aim) {
+        set_readout(&mut readout, String::new());
+        return;
+    }
+
+    let aim = target - ball_pos;
+    let readout_text = format!(
+        "Angle: {:.0}°  Power: {:.0}%",
+        aim.y.atan2(aim.x).to_degrees(),
+        shown_power / MAX_POWER * 100.
+    );
+    set_readout(&mut readout, readout_text);
+
+    if !firing {
         commands
             .spawn()
             .insert_bundle(lyon::GeometryBuilder::build_as(
                 &lyon::shapes::Circle {
-                    radius: 1. + power.0,
+                    radius: 1. + shown_power,
                     center: Vec2::ZERO,
                 },
                 lyon::DrawMode::Outlined {
@@ -373,52 +1345,85 @@ fn power_indicator(
                 },
                 Transform::default(),
             ))
-            .insert(Transform::from_xyz(pos2.x, pos2.y, 10.0))
+            .insert(Transform::from_xyz(cursor.x, cursor.y, 10.0))
             .insert(PowerIndicator);
+    }
 
-        if power.0 < 70. {
-            power.0 += 0.75;
+    if settings.trajectory_preview && mass.0.mass > 0. && target != ball_pos {
+        let velocity = launch_impulse(ball_pos, target, shown_power) / mass.0.mass;
+        for point in predict_trajectory(ball_pos, velocity, rapier_config.gravity, &env) {
+            commands
+                .spawn()
+                .insert_bundle(lyon::GeometryBuilder::build_as(
+                    &lyon::shapes::Circle {
+                        radius: 3.,
+                        center: Vec2::ZERO,
+                    },
+                    lyon::DrawMode::Fill(lyon::FillMode::color(Color::rgba(1.0, 1.0, 1.0, 0.8))),
+                    Transform::default(),
+                ))
+                .insert(Transform::from_xyz(point.x, point.y, 10.0))
+                .insert(TrajectoryDot);
         }
     }
 
-    if mouse.just_released(MouseButton::Right) {
-        let window = windows.get_primary().unwrap();
-        let pos1 = window.cursor_position().unwrap();
-        let pos2 = pos1 - Vec2::new(window.width() / 2., window.height() / 2.);
-
-        if let Ok(ent) = query.get_single_mut() {
-            commands.entity(ent).despawn();
-        }
+    if charging && power.0 < MAX_POWER {
+        power.0 += POWER_RATE;
+    }
 
+    if firing {
         launch_evt.send(LaunchEvent {
             power: power.0,
-            target: pos2,
+            target,
         });
 
-        power.0 = 0.;
+        *last_power = power.0;
+        // keyboard aiming keeps the power for fine tuning the next shot
+        if !keyboard {
+            power.0 = 0.;
+        }
+    }
+}
+
+fn set_readout(readout: &mut Query<&mut Text, With<AimReadout>>, value: String) {
+    for mut text in readout.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
     }
 }
 
 fn launch(
+    mut commands: Commands,
     mut launch_evt: ResMut<Events<LaunchEvent>>,
     mut game: ResMut<Game>,
-    mut ball: Query<(&mut ExternalImpulse, &Transform), With<Ball>>,
-    mut targets: Query<&mut ActiveEvents, With<Target>>,
+    mut ball: Query<(Entity, &mut ExternalImpulse, &Transform), With<Ball>>,
+    mut bodies: Query<&mut ActiveEvents, Or<(With<Target>, With<Block>, With<Ball>)>>,
+    mut readout: Query<&mut Text, With<AimReadout>>,
+    mut sounds: EventWriter<PlaySound>,
+    mut fired: EventWriter<ShotFired>,
 ) {
     let mut clear_force = true;
-    if let Ok((mut imp, trans)) = ball.get_single_mut() {
+    if let Ok((ball, mut imp, trans)) = ball.get_single_mut() {
         for ev in launch_evt.drain() {
-            // activate collisions on targets
-            for mut tgt in targets.iter_mut() {
-                *tgt = ActiveEvents::CONTACT_FORCE_EVENTS;
+            // activate collisions on targets, and on the rest for impact
+            // sounds. Their thresholds are higher so they can't break targets
+            for mut events in bodies.iter_mut() {
+                *events = ActiveEvents::CONTACT_FORCE_EVENTS;
             }
+            sounds.send(PlaySound(Sound::Launch));
+            // the wind only catches the ball once it is in the air
+            commands.entity(ball).insert(WindBlown);
 
             clear_force = false;
-            let vec = ev.target - Vec2::new(trans.translation.x, trans.translation.y);
-            let bottom = (vec.x.powi(2) + vec.y.powi(2)).sqrt();
-            let norm_vec = vec / bottom;
-            imp.impulse = norm_vec * ev.power * LAUNCH_FACTOR;
+            imp.impulse = launch_impulse(trans.translation.truncate(), ev.target, ev.power);
+            fired.send(ShotFired {
+                position: trans.translation.truncate(),
+                impulse: imp.impulse,
+            });
             game.state = LevelState::Launched;
+            game.shots_left -= 1;
+            set_readout(&mut readout, String::new());
         }
 
         if clear_force {
@@ -427,57 +1432,242 @@ fn launch(
     }
 }
 
-fn target_collisions(
+pub fn target_collisions(
     mut commands: Commands,
     mut contact_force_events: EventReader<ContactForceEvent>,
-    targets: Query<Entity, With<Target>>,
+    mut destroyed_events: EventWriter<TargetDestroyed>,
+    targets: Query<&Transform, With<Target>>,
 ) {
+    let mut destroyed = Vec::new();
     for collision_event in contact_force_events.iter() {
-        if targets.contains(collision_event.collider2) {
-            commands.entity(collision_event.collider2).despawn();
+        let target = collision_event.collider2;
+        if destroyed.contains(&target) {
+            continue;
+        }
+
+        if let Ok(trans) = targets.get(target) {
+            commands.entity(target).despawn_recursive();
+            destroyed.push(target);
+            destroyed_events.send(TargetDestroyed {
+                position: trans.translation.truncate(),
+            });
         }
     }
 }
 
+/// Removes targets and blocks that leave the world. Lost targets count as
+/// destroyed. Runs after the update's commands, so a target already hit this
+/// frame is gone and isn't counted twice
+pub fn out_of_bounds(
+    mut commands: Commands,
+    bounds: Res<WorldBounds>,
+    mut destroyed_events: EventWriter<TargetDestroyed>,
+    mut block_events: EventWriter<BlockDestroyed>,
+    bodies: Query<(Entity, &Transform, Option<&Target>), Or<(With<Target>, With<Block>)>>,
+) {
+    for (ent, trans, target) in bodies.iter() {
+        let pos = trans.translation.truncate();
+        if bounds.contains(pos) {
+            continue;
+        }
+
+        commands.entity(ent).despawn_recursive();
+        if target.is_some() {
+            destroyed_events.send(TargetDestroyed { position: pos });
+        } else {
+            block_events.send(BlockDestroyed { position: pos });
+        }
+    }
+}
+
+fn score_lost_blocks(mut run: ResMut<Run>, mut block_events: EventReader<BlockDestroyed>) {
+    for _ in block_events.iter() {
+        run.score += BLOCK_POINTS;
+    }
+}
+
+fn message_text(text: &str, asset_server: &AssetServer) -> TextBundle {
+    TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(35.),
+                right: Val::Px(15.),
+                ..default()
+            },
+            ..default()
+        },
+        text: Text::from_section(
+            text.to_string(),
+            TextStyle {
+                font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                font_size: 30.0,
+                color: Color::BLACK,
+            },
+        ),
+        ..default()
+    }
+}
+
 fn level_complete(
     mut commands: Commands,
     mut game: ResMut<Game>,
+    mut run: ResMut<Run>,
+    settings: Res<Settings>,
     asset_server: Res<AssetServer>,
     max_level: Res<MaxLevel>,
+    levels: Res<Levels>,
+    shared: Option<Res<SharedLevel>>,
     query: Query<&Target>,
+    mut sounds: EventWriter<PlaySound>,
+    mut ended: EventWriter<ShotEnded>,
+    mut completed: EventWriter<LevelCompleted>,
 ) {
-    if query.iter().len() == 0 {
+    let left = query.iter().len();
+    if left < game.targets_left {
+        let destroyed = (game.targets_left - left) as u32;
+        run.score += destroyed * TARGET_POINTS;
+        run.targets_destroyed += destroyed;
+        game.targets_left = left;
+    }
+
+    if left == 0 {
+        // the winning shot ends here, `shot_ended` stops with the targets
+        game.shot_time = 0.;
+        game.rest_time = 0.;
+        ended.send(ShotEnded {
+            shots_left: game.shots_left,
+        });
+
         game.state = LevelState::Complete;
+        run.score += game.shots_left as u32 * SHOT_BONUS;
+        run.towers_cleared += 1;
+        sounds.send(PlaySound(Sound::LevelComplete));
+        completed.send(LevelCompleted {
+            level: game.level,
+            score: run.score,
+        });
 
-        let text;
-        if game.level + 1 > max_level.0 {
-            text = "All levels complete! Press \"q\" to quit";
-            game.state = LevelState::LastLevelComplete;
+        let next = settings.binding(Action::NextLevel);
+        let text = match run.mode {
+            GameMode::Endless => format!("Tower down! Press \"{}\" for next tower", next),
+            GameMode::Campaign if game.level + 1 > max_level.0 => {
+                game.state = LevelState::LastLevelComplete;
+                format!(
+                    "All levels complete! Press \"{}\" to quit",
+                    settings.binding(Action::Quit)
+                )
+            }
+            GameMode::Campaign => format!("Press \"{}\" for next level", next),
+        };
+
+        let text = format!("{}\nCtrl+C copies the level code", text);
+        commands
+            .spawn_bundle(message_text(&text, &asset_server))
+            .insert(LevelNode);
+
+        // a shared level has no table of its own
+        if run.mode == GameMode::Campaign && shared.is_none() {
+            let level_score = run.score - game.level_start_score;
+            start_name_entry(
+                &mut commands,
+                &asset_server,
+                campaign_table(game.level, &levels.0[game.level]),
+                level_score,
+            );
+        }
+    }
+}
+
+/// Ends the shot once the ball has rested for a moment, left the screen or
+/// run out of time, then either readies the next shot or fails the level
+fn shot_ended(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut game: ResMut<Game>,
+    mut run: ResMut<Run>,
+    settings: Res<Settings>,
+    asset_server: Res<AssetServer>,
+    terrain: Res<Terrain>,
+    bounds: Res<WorldBounds>,
+    mut ball: Query<(Entity, &mut Transform, &mut Velocity), With<Ball>>,
+    targets: Query<&Target>,
+    mut ended: EventWriter<ShotEnded>,
+    mut failed: EventWriter<LevelFailed>,
+) {
+    if targets.iter().len() == 0 {
+        return;
+    }
+
+    if let Ok((ball, mut trans, mut vel)) = ball.get_single_mut() {
+        game.shot_time += time.delta_seconds();
+        if vel.linvel.length() < BALL_REST_SPEED {
+            game.rest_time += time.delta_seconds();
         } else {
-            text = "Press \"n\" for next level";
+            game.rest_time = 0.;
+        }
+
+        let gone = !bounds.contains(trans.translation.truncate());
+        if !gone && game.rest_time < BALL_REST_TIME && game.shot_time < SHOT_TIMEOUT {
+            return;
+        }
+
+        game.shot_time = 0.;
+        game.rest_time = 0.;
+        ended.send(ShotEnded {
+            shots_left: game.shots_left,
+        });
+
+        if game.shots_left > 0 {
+            *trans = Transform::from_translation(terrain.ball_start().extend(0.0));
+            *vel = Velocity::zero();
+            commands.entity(ball).remove::<WindBlown>();
+            game.state = LevelState::Prelaunch;
+            return;
+        }
+
+        game.state = LevelState::Failed;
+        failed.send(LevelFailed { level: game.level });
+
+        let text = match run.mode {
+            GameMode::Campaign => format!(
+                "Out of shots! Press \"{}\" to restart",
+                settings.binding(Action::Restart)
+            ),
+            GameMode::Endless => {
+                run.lives -= 1;
+                if run.lives == 0 {
+                    commands.insert_resource(NextState(AppState::GameOver));
+                    return;
+                }
+                format!(
+                    "The tower stands! Press \"{}\" for next tower",
+                    settings.binding(Action::NextLevel)
+                )
+            }
         };
 
+        let text = format!("{}\nCtrl+C copies the level code", text);
         commands
-            .spawn_bundle(TextBundle {
-                style: Style {
-                    position_type: PositionType::Absolute,
-                    position: UiRect {
-                        top: Val::Px(35.),
-                        right: Val::Px(15.),
-                        ..default()
-                    },
-                    ..default()
-                },
-                text: Text::from_section(
-                    text.to_string(),
-                    TextStyle {
-                        font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
-                        font_size: 30.0,
-                        color: Color::BLACK,
-                    },
-                ),
-                ..default()
-            })
+            .spawn_bundle(message_text(&text, &asset_server))
             .insert(LevelNode);
     }
 }
+
+fn update_hud(game: Res<Game>, run: Res<Run>, mut query: Query<&mut Text, With<HudText>>) {
+    if !game.is_changed() && !run.is_changed() {
+        return;
+    }
+
+    let hud = match run.mode {
+        GameMode::Campaign => format!("Score: {}  Undos: {}", run.score, game.undos_left),
+        GameMode::Endless => format!(
+            "Score: {}  Lives: {}  Shots: {}",
+            run.score, run.lives, game.shots_left
+        ),
+    };
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value = hud.clone();
+    }
+}
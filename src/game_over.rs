@@ -0,0 +1,112 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::cli::Options;
+use crate::game::{GameMode, Run};
+use crate::leaderboard::{start_name_entry, ENDLESS_TABLE};
+use crate::main_menu::{button_system, menu_button, on_btn_interact};
+
+#[derive(Component)]
+struct GameOverNode;
+
+#[derive(Component)]
+struct PlayAgainBtn;
+
+#[derive(Component)]
+struct MenuBtn;
+
+pub struct GameOverPlugin;
+
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(AppState::GameOver, setup_game_over)
+            .add_exit_system(AppState::GameOver, remove_game_over)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::GameOver)
+                    .with_system(btn_play_again.run_if(on_btn_interact::<PlayAgainBtn>))
+                    .with_system(btn_main_menu.run_if(on_btn_interact::<MenuBtn>))
+                    .with_system(button_system)
+                    .into(),
+            );
+    }
+}
+
+fn setup_game_over(mut commands: Commands, run: Res<Run>, asset_server: Res<AssetServer>) {
+    let summary = format!(
+        "Score: {}\nTowers cleared: {}\nTargets destroyed: {}",
+        run.score, run.towers_cleared, run.targets_destroyed
+    );
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(GameOverNode)
+        .with_children(|container| {
+            container.spawn_bundle(TextBundle {
+                text: Text::from_section(
+                    "Game Over",
+                    TextStyle {
+                        font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                        font_size: 80.0,
+                        color: Color::BLACK,
+                    },
+                ),
+                ..default()
+            });
+
+            container.spawn_bundle(TextBundle {
+                style: Style {
+                    margin: UiRect::new(Val::Px(0.), Val::Px(0.), Val::Px(20.), Val::Px(30.)),
+                    ..default()
+                },
+                text: Text::from_section(
+                    summary,
+                    TextStyle {
+                        font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                        font_size: 30.0,
+                        color: Color::BLACK,
+                    },
+                ),
+                ..default()
+            });
+
+            menu_button(container, &asset_server, "Play Again").insert(PlayAgainBtn);
+            menu_button(container, &asset_server, "Main Menu").insert(MenuBtn);
+        });
+
+    if run.mode == GameMode::Endless {
+        start_name_entry(
+            &mut commands,
+            &asset_server,
+            ENDLESS_TABLE.to_string(),
+            run.score,
+        );
+    }
+}
+
+fn remove_game_over(mut commands: Commands, query: Query<Entity, With<GameOverNode>>) {
+    for ent in query.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+}
+
+fn btn_play_again(mut commands: Commands, options: Res<Options>) {
+    commands.insert_resource(Run::new(GameMode::Endless, options.seed));
+    commands.insert_resource(NextState(AppState::InGame));
+}
+
+fn btn_main_menu(mut commands: Commands) {
+    commands.insert_resource(NextState(AppState::MainMenu));
+}
@@ -0,0 +1,163 @@
+use bevy::log::warn;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::constants::{
+    GEN_BEAM_HEIGHT, GEN_COLUMN_WIDTH, GEN_MAX_ATTEMPTS, GEN_MAX_BAY, GEN_MAX_STORY,
+    GEN_MAX_TOWER_WIDTH, GEN_MIN_BAY, GEN_MIN_STORY,
+};
+use crate::headless::is_stable;
+use crate::read_levels::{LevelData, TargetData, TowerData};
+
+/// Builds a random tower of stories, each made of columns capped by a beam,
+/// with targets sheltered between the columns. The same seed and difficulty
+/// always give the same level. Higher difficulty means taller towers, more
+/// stone and more targets
+pub fn generate_level(seed: u64, difficulty: usize) -> LevelData {
+    // reroll until the tower stands on its own, then try the simplest towers
+    let mut difficulties = vec![difficulty, 0];
+    difficulties.dedup();
+    for difficulty in difficulties {
+        for attempt in 0..GEN_MAX_ATTEMPTS {
+            let rng = &mut StdRng::seed_from_u64(seed.wrapping_add(attempt));
+            let level = build_tower(rng, difficulty);
+            if is_stable(&level) {
+                return level;
+            }
+        }
+    }
+
+    warn!("no stable tower found for seed {}", seed);
+    build_tower(&mut StdRng::seed_from_u64(seed), 0)
+}
+
+/// Seed of the `index`th level generated from `seed`, far enough apart that
+/// the rerolls of one level never repeat another's
+pub fn level_seed(seed: u64, index: usize) -> u64 {
+    seed.wrapping_add((index as u64).wrapping_mul(GEN_MAX_ATTEMPTS))
+}
+
+fn random_material(rng: &mut StdRng, difficulty: usize) -> String {
+    let stone_chance = (0.15 * difficulty as f64).min(0.8);
+    let kind = match (rng.gen_bool(stone_chance), rng.gen_bool(0.5)) {
+        (true, true) => "stone2",
+        (true, false) => "stone1",
+        (false, true) => "wood2",
+        (false, false) => "wood1",
+    };
+    kind.to_string()
+}
+
+// snap to 5px so generated levels read like the handwritten ones
+fn snap(v: f32) -> f32 {
+    (v / 5.).round() * 5.
+}
+
+fn build_tower(rng: &mut StdRng, difficulty: usize) -> LevelData {
+    let stories = rng.gen_range(1..=(2 + difficulty / 2).min(4));
+    let mut bays = rng.gen_range(1..=(1 + difficulty / 2).min(3));
+    let max_bay = (GEN_MAX_TOWER_WIDTH - GEN_COLUMN_WIDTH) / bays as f32;
+    let bay = snap(rng.gen_range(GEN_MIN_BAY..=max_bay.min(GEN_MAX_BAY)));
+    let target_chance = (0.4 + 0.1 * difficulty as f64).min(0.9);
+
+    let mut tower = Vec::new();
+    let mut targets = Vec::new();
+    let mut offset = 0.;
+    let mut floor = 0.;
+
+    for story in 0..stories {
+        // upper stories may be narrower, centered on the beam below
+        if story > 0 && bays > 1 && rng.gen_bool(0.5) {
+            bays -= 1;
+            offset += bay / 2.;
+        }
+
+        let height = snap(rng.gen_range(GEN_MIN_STORY..=GEN_MAX_STORY));
+        let width = bays as f32 * bay + GEN_COLUMN_WIDTH;
+        let column_kind = random_material(rng, difficulty);
+
+        for col in 0..=bays {
+            tower.push(TowerData {
+                kind: column_kind.clone(),
+                x: offset + col as f32 * bay,
+                y: floor,
+                h: height,
+                w: GEN_COLUMN_WIDTH,
+                rotation: 0.,
+            });
+        }
+
+        for b in 0..bays {
+            if rng.gen_bool(target_chance) {
+                targets.push(TargetData {
+                    x: offset + b as f32 * bay + (bay + GEN_COLUMN_WIDTH) / 2.,
+                    y: floor + 5.,
+                    path: None,
+                });
+            }
+        }
+
+        tower.push(TowerData {
+            kind: random_material(rng, difficulty),
+            x: offset,
+            y: floor + height,
+            h: GEN_BEAM_HEIGHT,
+            w: width,
+            rotation: 0.,
+        });
+
+        floor += height + GEN_BEAM_HEIGHT;
+    }
+
+    // every tower needs something to hit, the roof is the last resort
+    if targets.is_empty() || rng.gen_bool(0.1 * difficulty.min(5) as f64) {
+        targets.push(TargetData {
+            x: offset + (bays as f32 * bay + GEN_COLUMN_WIDTH) / 2.,
+            y: floor + 5.,
+            path: None,
+        });
+    }
+
+    LevelData {
+        tower,
+        targets,
+        ..Default::default()
+    }
+}
+
+/// Prints `count` generated levels as a JSON array, ready to paste into
+/// `levels.json`
+pub fn print_levels(seed: u64, count: usize) {
+    let levels: Vec<LevelData> = (0..count)
+        .map(|i| generate_level(level_seed(seed, i), i))
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&levels).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_levels::validate_level;
+
+    fn json(level: &LevelData) -> String {
+        serde_json::to_string(level).unwrap()
+    }
+
+    #[test]
+    fn a_seed_always_gives_the_same_level() {
+        for difficulty in [0, 3] {
+            let level = generate_level(42, difficulty);
+            assert_eq!(json(&level), json(&generate_level(42, difficulty)));
+            assert!(validate_level(&level).is_ok());
+        }
+        assert_ne!(json(&generate_level(42, 3)), json(&generate_level(43, 3)));
+    }
+
+    #[test]
+    fn level_seeds_leave_room_for_rerolls() {
+        assert_eq!(level_seed(7, 0), 7);
+        assert_eq!(level_seed(7, 2) - level_seed(7, 1), GEN_MAX_ATTEMPTS);
+        // no overflow near the top of the range
+        assert_eq!(level_seed(u64::MAX, 1), GEN_MAX_ATTEMPTS - 1);
+    }
+}
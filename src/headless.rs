@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::ecs::schedule::{SingleThreadedExecutor, StageLabelId};
+use bevy::ecs::system::CommandQueue;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::dynamics::RigidBodyHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::capture::capture_level;
+use crate::constants::{
+    HEADLESS_DT, HEADLESS_SHOT_FRAMES, HEADLESS_WARMUP_FRAMES, HEIGHT, PIXELS_PER_METER,
+    SETTLE_COLLAPSE_DRIFT, SETTLE_COLLAPSE_TILT, SETTLE_MAX_FRAMES, STABLE_FRAMES,
+    STABLE_MAX_DRIFT, STABLE_MAX_TILT, WIDTH,
+};
+use crate::environment::{environment_forces, Environment, WindBlown};
+use crate::game::{
+    launch_impulse, out_of_bounds, spawn_level_bodies, target_collisions, Ball, Block,
+    BlockDestroyed, BodyId, CurrentLevel, Target, TargetDestroyed,
+};
+use crate::joints::break_joints;
+use crate::movers::move_kinematic;
+use crate::read_levels::LevelData;
+use crate::terrain::{Terrain, WorldBounds};
+
+/// A launch described by its angle (degrees, counter-clockwise from the
+/// positive x axis) and power, the same inputs a player gives to `LaunchEvent`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Shot {
+    pub angle: f32,
+    pub power: f32,
+}
+
+impl Shot {
+    pub fn direction(&self) -> Vec2 {
+        let rad = self.angle.to_radians();
+        Vec2::new(rad.cos(), rad.sin())
+    }
+}
+
+/// A level running without a window, stepped at a fixed timestep so runs
+/// are repeatable. The game steps with the frame time instead, so a shot can
+/// land a little differently in play than here: treat the solver's results
+/// as a close estimate rather than a replay of what a player will see
+pub struct HeadlessLevel {
+    app: App,
+}
+
+impl HeadlessLevel {
+    /// Spawns the level and lets the tower land before anything is fired at
+    /// it, like a player would
+    pub fn new(level: &LevelData) -> Self {
+        let mut headless = HeadlessLevel::spawn(level);
+        headless.step(HEADLESS_WARMUP_FRAMES);
+        headless
+    }
+
+    /// Spawns the level without stepping the simulation
+    pub fn spawn(level: &LevelData) -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .insert_resource(RapierConfiguration {
+                timestep_mode: TimestepMode::Fixed {
+                    dt: HEADLESS_DT,
+                    substeps: 1,
+                },
+                ..default()
+            })
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(
+                PIXELS_PER_METER,
+            ))
+            .add_event::<TargetDestroyed>()
+            .add_event::<BlockDestroyed>()
+            .add_system(target_collisions)
+            .add_system(environment_forces)
+            .add_system(move_kinematic)
+            .add_system(break_joints)
+            .add_system_to_stage(CoreStage::PostUpdate, out_of_bounds)
+            .insert_resource(CurrentLevel(level.clone()));
+
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &app.world);
+        spawn_level_bodies(&mut commands, level, WIDTH, HEIGHT);
+        queue.apply(&mut app.world);
+        run_on_one_thread(&mut app.schedule);
+
+        HeadlessLevel { app }
+    }
+
+    pub fn step(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.app.update();
+        }
+    }
+
+    pub fn targets_left(&mut self) -> usize {
+        self.app
+            .world
+            .query_filtered::<Entity, With<Target>>()
+            .iter(&self.app.world)
+            .count()
+    }
+
+    /// Transforms of every tower block and target, in a stable order
+    pub fn body_transforms(&mut self) -> Vec<Transform> {
+        self.app
+            .world
+            .query_filtered::<&Transform, Or<(With<Block>, With<Target>)>>()
+            .iter(&self.app.world)
+            .copied()
+            .collect()
+    }
+
+    /// Steps until everything has come to rest, for at most `max_frames`.
+    /// False if it was still moving
+    pub fn settle(&mut self, max_frames: usize) -> bool {
+        for _ in 0..max_frames {
+            if self.settled() {
+                return true;
+            }
+            self.step(1);
+        }
+        self.settled()
+    }
+
+    /// Where each block still in the world is
+    fn blocks(&mut self) -> HashMap<BodyId, Transform> {
+        self.app
+            .world
+            .query_filtered::<(&BodyId, &Transform), With<Block>>()
+            .iter(&self.app.world)
+            .map(|(id, trans)| (*id, *trans))
+            .collect()
+    }
+
+    /// The level with the bodies where they are now, see `capture_level`
+    pub fn capture(&mut self) -> LevelData {
+        capture_level(&mut self.app.world)
+    }
+
+    /// True once every dynamic body still in the world has come to rest,
+    /// leaving out the ones the level itself keeps moving
+    pub fn settled(&mut self) -> bool {
+        let forced = self.forced_bodies();
+        let context = self.app.world.resource::<RapierContext>();
+        let bounds = self.app.world.resource::<WorldBounds>();
+        context.bodies.iter().all(|(handle, body)| {
+            let pos = body.translation() * PIXELS_PER_METER;
+            let off_world = !bounds.contains(Vec2::new(pos.x, pos.y));
+
+            !body.is_dynamic()
+                || off_world
+                || forced.contains(&handle)
+                || body.is_sleeping()
+                || (body.linvel().norm() < 0.05 && body.angvel().abs() < 0.05)
+        })
+    }
+
+    /// Blocks and targets that never come to rest on their own: light ones
+    /// pushed by the wind and anything touching a mover
+    fn forced_bodies(&mut self) -> HashSet<RigidBodyHandle> {
+        let world = &mut self.app.world;
+        let pieces: HashSet<Entity> = world
+            .query_filtered::<Entity, Or<(With<Block>, With<Target>)>>()
+            .iter(world)
+            .collect();
+        let blown: Vec<Entity> = if world.resource::<Environment>().wind.is_some() {
+            world
+                .query_filtered::<Entity, With<WindBlown>>()
+                .iter(world)
+                .filter(|ent| pieces.contains(ent))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let context = world.resource::<RapierContext>();
+        let mut forced: HashSet<RigidBodyHandle> = blown
+            .iter()
+            .filter_map(|ent| context.entity2body().get(ent).copied())
+            .collect();
+
+        let is_mover = |handle| {
+            context
+                .bodies
+                .get(handle)
+                .is_some_and(|body| body.is_kinematic())
+        };
+        let is_piece = |handle| {
+            context
+                .rigid_body_entity(handle)
+                .is_some_and(|ent| pieces.contains(&ent))
+        };
+        for pair in context.narrow_phase.contact_pairs() {
+            if !pair.has_any_active_contact {
+                continue;
+            }
+            let parent = |collider| context.colliders.get(collider).and_then(|c| c.parent());
+            if let (Some(a), Some(b)) = (parent(pair.collider1), parent(pair.collider2)) {
+                for (mover, other) in [(a, b), (b, a)] {
+                    if is_mover(mover) && is_piece(other) {
+                        forced.insert(other);
+                    }
+                }
+            }
+        }
+        forced
+    }
+
+    /// Puts the ball back at its start, fires it and runs the simulation until
+    /// everything settles, returning the number of targets left
+    pub fn fire(&mut self, shot: &Shot) -> usize {
+        let start = self.app.world.resource::<Terrain>().ball_start();
+        let world = &mut self.app.world;
+
+        let mut ball = world
+            .query_filtered::<(&mut Transform, &mut Velocity, &mut ExternalImpulse), With<Ball>>();
+        for (mut trans, mut vel, mut imp) in ball.iter_mut(world) {
+            *trans = Transform::from_translation(start.extend(0.0));
+            *vel = Velocity::zero();
+            imp.impulse = launch_impulse(start, start + shot.direction(), shot.power);
+        }
+
+        // the wind only catches the ball once it is in the air
+        let balls: Vec<Entity> = world
+            .query_filtered::<Entity, With<Ball>>()
+            .iter(world)
+            .collect();
+        for ent in balls {
+            world.entity_mut(ent).insert(WindBlown);
+        }
+
+        // activate collisions on targets
+        let mut targets = world.query_filtered::<&mut ActiveEvents, With<Target>>();
+        for mut tgt in targets.iter_mut(world) {
+            *tgt = ActiveEvents::CONTACT_FORCE_EVENTS;
+        }
+
+        self.app.update();
+
+        let world = &mut self.app.world;
+        let mut impulses = world.query_filtered::<&mut ExternalImpulse, With<Ball>>();
+        for mut imp in impulses.iter_mut(world) {
+            imp.impulse = Vec2::ZERO;
+        }
+
+        for _ in 0..HEADLESS_SHOT_FRAMES {
+            self.app.update();
+            if self.targets_left() == 0 || self.settled() {
+                break;
+            }
+        }
+
+        self.targets_left()
+    }
+}
+
+/// Runs every stage of `schedule` on the thread updating it. Levels are
+/// settled on background threads, and one that waited there for the compute
+/// threads could wait forever on a game system waiting for the level
+fn run_on_one_thread(schedule: &mut Schedule) {
+    let labels: Vec<StageLabelId> = schedule.iter_stages().map(|(label, _)| label).collect();
+    for label in labels {
+        if let Some(stage) = schedule.get_stage_mut::<SystemStage>(&label) {
+            stage.set_executor(Box::new(SingleThreadedExecutor));
+        } else if let Some(inner) = schedule.get_stage_mut::<Schedule>(&label) {
+            run_on_one_thread(inner);
+        }
+    }
+}
+
+/// Lets a level run untouched and checks that no block or target drifts or
+/// tips over on its own
+pub fn is_stable(level: &LevelData) -> bool {
+    let mut sim = HeadlessLevel::spawn(level);
+    sim.step(1);
+    let before = sim.body_transforms();
+    sim.step(STABLE_FRAMES);
+    let after = sim.body_transforms();
+
+    before.len() == after.len()
+        && before.iter().zip(after.iter()).all(|(a, b)| {
+            a.translation.distance(b.translation) < STABLE_MAX_DRIFT
+                && a.rotation.angle_between(b.rotation) < STABLE_MAX_TILT
+        })
+}
+
+/// A level as it is once its tower has come to rest on its own
+pub struct Settled {
+    pub level: LevelData,
+    /// False if it was still moving when it was given up on
+    pub rested: bool,
+    /// A block fell, tipped or slid further than settling onto its feet
+    /// would move it
+    pub collapsed: bool,
+    pub targets_lost: usize,
+}
+
+/// Lets a level run untouched until its tower rests, so overlapping or
+/// floating blocks have sorted themselves out before anyone sees them.
+/// Nothing has collisions reported yet, as before the first shot, so only
+/// falling out of the world destroys anything
+pub fn settle_level(level: &LevelData) -> Settled {
+    let mut sim = HeadlessLevel::spawn(level);
+    sim.step(1);
+    let before = sim.blocks();
+    let rested = sim.settle(SETTLE_MAX_FRAMES);
+    let after = sim.blocks();
+
+    let collapsed = before.iter().any(|(id, a)| match after.get(id) {
+        Some(b) => {
+            a.translation.distance(b.translation) > SETTLE_COLLAPSE_DRIFT
+                || a.rotation.angle_between(b.rotation) > SETTLE_COLLAPSE_TILT
+        }
+        None => true,
+    });
+
+    Settled {
+        level: sim.capture(),
+        rested,
+        collapsed,
+        targets_lost: level.targets.len() - sim.targets_left(),
+    }
+}
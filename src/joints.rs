@@ -0,0 +1,134 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude as lyon;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::dynamics::{JointAxesMask, JointAxis};
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::constants::{GLUE_STRENGTH, ROPE_SWIVEL_MASS};
+use crate::game::Paused;
+use crate::read_levels::JointData;
+
+/// A joint that breaks once it is pulled harder than this
+#[derive(Component)]
+pub struct Glue(pub f32);
+
+/// The line drawn for a rope, from the centre of `a` to `anchor` in `b`'s
+/// frame
+#[derive(Component)]
+pub struct Rope {
+    pub a: Entity,
+    pub b: Entity,
+    pub anchor: Vec2,
+}
+
+/// The Rapier joint described by `joint`, meeting at `anchor_a` on body `a`
+/// and `anchor_b` on body `b`, each in the body's own frame, along with the
+/// glue to insert next to it if it can break. `angle_a` and `angle_b` are
+/// the bodies' rotations, which glue holds them at. A rope instead ties the
+/// centre of `a` to a swivel hinged to `b` at the anchor, see `rope_swivel`
+pub fn build_joint(
+    joint: &JointData,
+    anchor_a: Vec2,
+    anchor_b: Vec2,
+    angle_a: f32,
+    angle_b: f32,
+) -> (GenericJoint, Option<Glue>) {
+    match joint.kind.as_str() {
+        "glue" => (
+            FixedJointBuilder::new()
+                .local_basis1(-angle_b)
+                .local_basis2(-angle_a)
+                .local_anchor1(anchor_b)
+                .local_anchor2(anchor_a)
+                .into(),
+            Some(Glue(joint.strength.unwrap_or(GLUE_STRENGTH))),
+        ),
+        "rope" => {
+            // `a` slides along a line out from the swivel, which turns with
+            // it, no further than `length`
+            let hanging = Quat::from_rotation_z(angle_a) * -anchor_a.extend(0.);
+            let length = joint.length.unwrap_or_else(|| anchor_a.length());
+            (
+                GenericJointBuilder::new(JointAxesMask::Y)
+                    .local_axis1(hanging.truncate().try_normalize().unwrap_or(-Vec2::Y))
+                    .limits(JointAxis::X, [0., length])
+                    .into(),
+                None,
+            )
+        }
+        _ => {
+            let mut hinge = RevoluteJointBuilder::new()
+                .local_anchor1(anchor_b)
+                .local_anchor2(anchor_a);
+            if let Some([min, max]) = joint.limits {
+                hinge = hinge.limits([min.to_radians(), max.to_radians()]);
+            }
+            (hinge.into(), None)
+        }
+    }
+}
+
+/// A free turning body at `anchor` on `b`, in `b`'s frame, for a rope to
+/// hang from, so the rope can swing whatever `b` is
+pub fn rope_swivel(b: Entity, anchor: Vec2) -> impl Bundle {
+    (
+        RigidBody::Dynamic,
+        AdditionalMassProperties::MassProperties(MassProperties {
+            mass: ROPE_SWIVEL_MASS,
+            principal_inertia: ROPE_SWIVEL_MASS,
+            ..Default::default()
+        }),
+        ImpulseJoint::new(b, RevoluteJointBuilder::new().local_anchor1(anchor)),
+    )
+}
+
+pub struct JointsPlugin;
+
+impl Plugin for JointsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(AppState::InGame)
+                .run_unless_resource_exists::<Paused>()
+                .with_system(break_joints)
+                .with_system(draw_ropes)
+                .into(),
+        );
+    }
+}
+
+/// Removes glue that took more force than it holds during the last step
+pub fn break_joints(
+    mut commands: Commands,
+    context: Res<RapierContext>,
+    glue: Query<(Entity, &Glue, &RapierImpulseJointHandle)>,
+) {
+    let dt = context.integration_parameters.dt;
+    for (ent, glue, handle) in glue.iter() {
+        if let Some(joint) = context.impulse_joints.get(handle.0) {
+            let force = Vec2::new(joint.impulses.x, joint.impulses.y).length() / dt;
+            if force > glue.0 {
+                commands.entity(ent).despawn_recursive();
+            }
+        }
+    }
+}
+
+fn draw_ropes(
+    mut commands: Commands,
+    mut ropes: Query<(Entity, &Rope, &mut lyon::Path)>,
+    bodies: Query<&GlobalTransform, Without<Rope>>,
+) {
+    for (ent, rope, mut path) in ropes.iter_mut() {
+        match (bodies.get(rope.a), bodies.get(rope.b)) {
+            (Ok(a), Ok(b)) => {
+                let from = a.translation().truncate();
+                let to = b.mul_vec3(rope.anchor.extend(0.)).truncate();
+                *path = lyon::ShapePath::build_as(&lyon::shapes::Line(from, to));
+            }
+            // one end was destroyed
+            _ => commands.entity(ent).despawn(),
+        }
+    }
+}
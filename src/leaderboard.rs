@@ -0,0 +1,475 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::AppState;
+use crate::constants::{
+    HIGH_SCORE_ENTRIES, MAX_NAME_LEN, SCORES_LOCK_RETRIES, SCORES_LOCK_STALE, SCORES_PATH,
+};
+use crate::game::Levels;
+use crate::main_menu::{button_system, menu_button, on_btn_interact};
+use crate::read_levels::LevelData;
+
+pub const ENDLESS_TABLE: &str = "endless";
+
+/// Table of a campaign level, named by the level itself as well as its
+/// place, so the levels of another file or pack don't share its scores
+pub fn campaign_table(index: usize, level: &LevelData) -> String {
+    let json = serde_json::to_vec(level).unwrap();
+    format!("campaign-level-{}-{:08x}", index, crc32fast::hash(&json))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScoreEntry {
+    pub name: String,
+    pub score: u32,
+}
+
+/// High scores kept on disk, one table per campaign level and one for
+/// endless mode, each sorted best first
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Leaderboard {
+    #[serde(default)]
+    pub last_name: String,
+    #[serde(default)]
+    pub tables: BTreeMap<String, Vec<ScoreEntry>>,
+}
+
+impl Leaderboard {
+    /// Reads the score file. A file that can't be parsed is moved aside so
+    /// the next save starts a fresh table instead of failing forever
+    pub fn load() -> Self {
+        match Leaderboard::read() {
+            Ok(board) => board,
+            // only move the file under the lock, another game may be saving
+            Err(_) => match ScoreLock::acquire() {
+                Ok(_lock) => Leaderboard::load_locked(),
+                Err(_) => Leaderboard::default(),
+            },
+        }
+    }
+
+    fn read() -> Result<Self, serde_json::Error> {
+        match fs::read_to_string(SCORES_PATH) {
+            Ok(json) => serde_json::from_str(&json),
+            Err(_) => Ok(Leaderboard::default()),
+        }
+    }
+
+    /// `load` for a caller already holding the lock
+    fn load_locked() -> Self {
+        match Leaderboard::read() {
+            Ok(board) => board,
+            Err(err) => {
+                let backup = format!("{}.corrupt", SCORES_PATH);
+                warn!(
+                    "high scores are corrupted ({}), moving them to {}",
+                    err, backup
+                );
+                let _ = fs::rename(SCORES_PATH, backup);
+                Leaderboard::default()
+            }
+        }
+    }
+
+    /// Writes to a temporary file first so a crash mid-write can't leave a
+    /// half written score file behind
+    fn save(&self) -> io::Result<()> {
+        let tmp = format!("{}.tmp", SCORES_PATH);
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, SCORES_PATH)
+    }
+
+    pub fn entries(&self, table: &str) -> &[ScoreEntry] {
+        self.tables.get(table).map(|t| t.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn qualifies(&self, table: &str, score: u32) -> bool {
+        let entries = self.entries(table);
+        score > 0
+            && (entries.len() < HIGH_SCORE_ENTRIES
+                || entries.last().is_none_or(|e| score > e.score))
+    }
+
+    fn insert(&mut self, table: &str, entry: ScoreEntry) {
+        let entries = self.tables.entry(table.to_string()).or_default();
+        let pos = entries
+            .iter()
+            .position(|e| entry.score > e.score)
+            .unwrap_or(entries.len());
+        entries.insert(pos, entry);
+        entries.truncate(HIGH_SCORE_ENTRIES);
+    }
+
+    /// Adds a score while holding the lock, re-reading the file first so
+    /// scores saved by another running game aren't lost
+    pub fn submit(table: &str, name: &str, score: u32) -> io::Result<()> {
+        let _lock = ScoreLock::acquire()?;
+        let mut board = Leaderboard::load_locked();
+        board.insert(
+            table,
+            ScoreEntry {
+                name: name.to_string(),
+                score,
+            },
+        );
+        board.last_name = name.to_string();
+        board.save()
+    }
+}
+
+/// Exclusive access to the score file, released when dropped
+struct ScoreLock {
+    path: String,
+}
+
+impl ScoreLock {
+    fn acquire() -> io::Result<Self> {
+        let path = format!("{}.lock", SCORES_PATH);
+
+        for _ in 0..SCORES_LOCK_RETRIES {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(ScoreLock { path }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    // a game that crashed while saving leaves its lock behind
+                    let stale = fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| SystemTime::now().duration_since(t).ok())
+                        .is_some_and(|age| age > SCORES_LOCK_STALE);
+                    if stale {
+                        let _ = fs::remove_file(&path);
+                    } else {
+                        thread::sleep(Duration::from_millis(20));
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "high score file is locked by another game",
+        ))
+    }
+}
+
+impl Drop for ScoreLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A qualifying score waiting for the player to type their name
+pub struct NameEntry {
+    pub table: String,
+    pub score: u32,
+    pub name: String,
+}
+
+#[derive(Component)]
+struct NameEntryNode;
+
+#[derive(Component)]
+struct NameEntryText;
+
+#[derive(Component)]
+struct LeaderboardNode;
+
+#[derive(Component)]
+struct BackBtn;
+
+pub struct LeaderboardPlugin;
+
+impl Plugin for LeaderboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(AppState::Leaderboard, setup_leaderboard)
+            .add_exit_system(AppState::Leaderboard, remove_leaderboard)
+            .add_exit_system(AppState::InGame, cancel_name_entry)
+            .add_exit_system(AppState::GameOver, cancel_name_entry)
+            .add_system(name_entry_input.run_if_resource_exists::<NameEntry>())
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::Leaderboard)
+                    .with_system(btn_back.run_if(on_btn_interact::<BackBtn>))
+                    .with_system(button_system)
+                    .into(),
+            );
+    }
+}
+
+/// Asks for the player's name if `score` makes it onto `table`
+pub fn start_name_entry(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    table: String,
+    score: u32,
+) {
+    let board = Leaderboard::load();
+    if !board.qualifies(&table, score) {
+        return;
+    }
+
+    let font = asset_server.load("fonts/JetBrainsMono-Bold.ttf");
+    let style = TextStyle {
+        font,
+        font_size: 30.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(40.),
+                    left: Val::Px(225.),
+                    ..default()
+                },
+                size: Size::new(Val::Px(450.), Val::Px(140.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+            ..default()
+        })
+        .insert(NameEntryNode)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section(format!("New high score: {}", score), style.clone()),
+                ..default()
+            });
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::from_section(format!("{}_", board.last_name), style.clone()),
+                    ..default()
+                })
+                .insert(NameEntryText);
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section("Type your name, Enter to save", style),
+                ..default()
+            });
+        });
+
+    commands.insert_resource(NameEntry {
+        table,
+        score,
+        name: board.last_name,
+    });
+}
+
+fn name_entry_input(
+    mut commands: Commands,
+    mut entry: ResMut<NameEntry>,
+    mut chars: EventReader<ReceivedCharacter>,
+    keyboard: Res<Input<KeyCode>>,
+    mut text: Query<&mut Text, With<NameEntryText>>,
+    nodes: Query<Entity, With<NameEntryNode>>,
+) {
+    for ev in chars.iter() {
+        if !ev.char.is_control() && entry.name.chars().count() < MAX_NAME_LEN {
+            entry.name.push(ev.char);
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::Back) {
+        entry.name.pop();
+    }
+
+    if keyboard.just_pressed(KeyCode::Return) && !entry.name.trim().is_empty() {
+        if let Err(err) = Leaderboard::submit(&entry.table, entry.name.trim(), entry.score) {
+            warn!("could not save high score: {}", err);
+        }
+
+        for ent in nodes.iter() {
+            commands.entity(ent).despawn_recursive();
+        }
+        commands.remove_resource::<NameEntry>();
+        return;
+    }
+
+    if entry.is_changed() {
+        for mut text in text.iter_mut() {
+            text.sections[0].value = format!("{}_", entry.name);
+        }
+    }
+}
+
+fn cancel_name_entry(mut commands: Commands, nodes: Query<Entity, With<NameEntryNode>>) {
+    for ent in nodes.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+    commands.remove_resource::<NameEntry>();
+}
+
+fn table_text(entries: &[ScoreEntry], count: usize) -> String {
+    if entries.is_empty() {
+        return "  -\n".to_string();
+    }
+
+    entries
+        .iter()
+        .take(count)
+        .enumerate()
+        .map(|(i, e)| format!("{:>2}. {:<12} {:>6}\n", i + 1, e.name, e.score))
+        .collect()
+}
+
+fn setup_leaderboard(mut commands: Commands, asset_server: Res<AssetServer>, levels: Res<Levels>) {
+    let board = Leaderboard::load();
+
+    let endless = format!("Endless\n{}", table_text(board.entries(ENDLESS_TABLE), 10));
+
+    // only the levels being played, not those of other files
+    let mut campaign = "Campaign\n".to_string();
+    for (i, level) in levels.0.iter().enumerate() {
+        let entries = board.entries(&campaign_table(i, level));
+        if !entries.is_empty() {
+            campaign.push_str(&format!("Level {}\n{}", i, table_text(entries, 3)));
+        }
+    }
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/JetBrainsMono-Regular.ttf"),
+        font_size: 18.0,
+        color: Color::BLACK,
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(LeaderboardNode)
+        .with_children(|container| {
+            container.spawn_bundle(TextBundle {
+                text: Text::from_section(
+                    "High Scores",
+                    TextStyle {
+                        font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                        font_size: 60.0,
+                        color: Color::BLACK,
+                    },
+                ),
+                ..default()
+            });
+
+            container
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        margin: UiRect::all(Val::Px(10.)),
+                        align_items: AlignItems::FlexStart,
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|columns| {
+                    for text in [endless, campaign] {
+                        columns.spawn_bundle(TextBundle {
+                            style: Style {
+                                margin: UiRect::all(Val::Px(20.)),
+                                ..default()
+                            },
+                            text: Text::from_section(text, style.clone()),
+                            ..default()
+                        });
+                    }
+                });
+
+            menu_button(container, &asset_server, "Back").insert(BackBtn);
+        });
+}
+
+fn remove_leaderboard(mut commands: Commands, query: Query<Entity, With<LeaderboardNode>>) {
+    for ent in query.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+}
+
+fn btn_back(mut commands: Commands) {
+    commands.insert_resource(NextState(AppState::MainMenu));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, score: u32) -> ScoreEntry {
+        ScoreEntry {
+            name: name.to_string(),
+            score,
+        }
+    }
+
+    #[test]
+    fn scores_are_kept_best_first() {
+        let mut board = Leaderboard::default();
+        board.insert(ENDLESS_TABLE, entry("a", 200));
+        board.insert(ENDLESS_TABLE, entry("b", 500));
+        board.insert(ENDLESS_TABLE, entry("c", 300));
+        // a tie goes below the score already there
+        board.insert(ENDLESS_TABLE, entry("d", 300));
+
+        let names: Vec<&str> = board
+            .entries(ENDLESS_TABLE)
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(names, ["b", "c", "d", "a"]);
+        assert!(board
+            .entries(&campaign_table(0, &LevelData::default()))
+            .is_empty());
+    }
+
+    #[test]
+    fn full_tables_take_only_better_scores() {
+        let mut board = Leaderboard::default();
+        assert!(!board.qualifies(ENDLESS_TABLE, 0));
+        assert!(board.qualifies(ENDLESS_TABLE, 1));
+
+        for i in 0..HIGH_SCORE_ENTRIES as u32 {
+            board.insert(ENDLESS_TABLE, entry("a", 100 + i));
+        }
+        assert!(!board.qualifies(ENDLESS_TABLE, 100));
+        assert!(board.qualifies(ENDLESS_TABLE, 101));
+
+        board.insert(ENDLESS_TABLE, entry("b", 1000));
+        let entries = board.entries(ENDLESS_TABLE);
+        assert_eq!(entries.len(), HIGH_SCORE_ENTRIES);
+        assert_eq!(entries[0].name, "b");
+        assert_eq!(entries.last().unwrap().score, 101);
+    }
+
+    #[test]
+    fn campaign_tables_follow_the_level() {
+        let level = LevelData::default();
+        let mut other = LevelData::default();
+        other.targets.push(crate::read_levels::TargetData {
+            x: 0.,
+            y: 0.,
+            path: None,
+        });
+
+        assert_eq!(campaign_table(2, &level), campaign_table(2, &level));
+        assert_ne!(campaign_table(2, &level), campaign_table(3, &level));
+        assert_ne!(campaign_table(2, &level), campaign_table(2, &other));
+    }
+}
@@ -0,0 +1,395 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::constants::{LEVEL_CODE_MAX_SIZE, LEVEL_CODE_PREFIX, LEVEL_CODE_VERSION};
+use crate::game::{CurrentLevel, GameMode, LevelNode, Paused, Run, SharedLevel};
+use crate::leaderboard::NameEntry;
+use crate::read_levels::{validate_level, LevelData};
+
+/// Characters of a code shown while it is typed, the rest scrolls off
+const SHOWN_CHARS: usize = 36;
+
+/// A level as a line of text to send to someone: `TTT1-` and then, in URL
+/// safe base64, a CRC32 of the compressed level and the level as deflated
+/// JSON
+pub fn encode_level(level: &LevelData) -> String {
+    let json = serde_json::to_vec(level).unwrap();
+    let compressed = miniz_oxide::deflate::compress_to_vec(&json, 9);
+
+    let mut bytes = crc32fast::hash(&compressed).to_be_bytes().to_vec();
+    bytes.extend(compressed);
+    format!(
+        "{}{}-{}",
+        LEVEL_CODE_PREFIX,
+        LEVEL_CODE_VERSION,
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// The level in a code from `encode_level`. Whitespace is ignored, so a code
+/// wrapped over several lines still reads
+pub fn decode_level(code: &str) -> Result<LevelData, String> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let (version, data) = code
+        .strip_prefix(LEVEL_CODE_PREFIX)
+        .and_then(|rest| rest.split_once('-'))
+        .ok_or_else(|| {
+            format!(
+                "not a level code, they start with \"{}\"",
+                LEVEL_CODE_PREFIX
+            )
+        })?;
+
+    let version: u32 = version
+        .parse()
+        .map_err(|_| format!("not a level code, bad version \"{}\"", version))?;
+    if version > LEVEL_CODE_VERSION {
+        return Err(format!(
+            "the code is from a newer version of the game (version {}, this one reads up to {})",
+            version, LEVEL_CODE_VERSION
+        ));
+    }
+    if version != LEVEL_CODE_VERSION {
+        return Err(format!("version {} codes are no longer supported", version));
+    }
+
+    let damaged = |what: &str| format!("the code is damaged ({}), check it was copied whole", what);
+    let bytes =
+        base64::decode_config(data, base64::URL_SAFE_NO_PAD).map_err(|_| damaged("not base64"))?;
+    if bytes.len() < 4 {
+        return Err(damaged("too short"));
+    }
+    let (crc, compressed) = bytes.split_at(4);
+    if crc32fast::hash(compressed).to_be_bytes() != crc {
+        return Err(damaged("checksum doesn't match"));
+    }
+    let json = miniz_oxide::inflate::decompress_to_vec_with_limit(compressed, LEVEL_CODE_MAX_SIZE)
+        .map_err(|_| damaged("can't be unpacked"))?;
+
+    let level: LevelData = serde_json::from_slice(&json)
+        .map_err(|err| format!("the code doesn't hold a level: {}", err))?;
+    validate_level(&level).map_err(|err| format!("the code's level can't be played: {}", err))?;
+    Ok(level)
+}
+
+/// Puts a code on the system clipboard, to paste it where it's to be sent
+pub fn copy_to_clipboard(code: &str) -> Result<(), String> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(code.to_string()))
+        .map_err(|err| format!("can't copy to the clipboard: {}", err))
+}
+
+pub fn paste_from_clipboard() -> Result<String, String> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|err| format!("can't paste from the clipboard: {}", err))
+}
+
+/// Whether Ctrl, or Cmd on a Mac, is held for a shortcut
+pub fn modifier_pressed(keyboard: &Input<KeyCode>) -> bool {
+    keyboard.any_pressed([
+        KeyCode::LControl,
+        KeyCode::RControl,
+        KeyCode::LWin,
+        KeyCode::RWin,
+    ])
+}
+
+/// A code being typed or pasted in to play
+pub struct CodeEntry {
+    pub code: String,
+    pub error: Option<String>,
+}
+
+#[derive(Component)]
+struct CodeEntryNode;
+
+#[derive(Component)]
+struct CodeEntryText;
+
+/// Says whether the level's code was copied
+#[derive(Component)]
+struct CopiedText;
+
+pub struct LevelCodePlugin;
+
+impl Plugin for LevelCodePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_exit_system(AppState::MainMenu, cancel_code_entry)
+            .add_system(code_entry_input.run_if_resource_exists::<CodeEntry>())
+            .add_system(
+                copy_level_code
+                    .run_in_state(AppState::InGame)
+                    .run_unless_resource_exists::<Paused>()
+                    .run_unless_resource_exists::<NameEntry>(),
+            );
+    }
+}
+
+/// Opens a box to type or paste a level code into, Enter plays it
+pub fn start_code_entry(commands: &mut Commands, asset_server: &AssetServer) {
+    let font = asset_server.load("fonts/JetBrainsMono-Bold.ttf");
+    let style = TextStyle {
+        font,
+        font_size: 24.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(40.),
+                    left: Val::Px(150.),
+                    ..default()
+                },
+                size: Size::new(Val::Px(600.), Val::Px(140.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+            ..default()
+        })
+        .insert(CodeEntryNode)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    style: Style {
+                        max_size: Size::new(Val::Px(580.), Val::Undefined),
+                        ..default()
+                    },
+                    text: Text::from_section("_", style.clone()),
+                    ..default()
+                })
+                .insert(CodeEntryText);
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section(
+                    "Paste a level code (Ctrl+V), Enter to play, Esc to cancel",
+                    style,
+                ),
+                ..default()
+            });
+        });
+
+    commands.insert_resource(CodeEntry {
+        code: String::new(),
+        error: None,
+    });
+}
+
+fn code_entry_input(
+    mut commands: Commands,
+    mut entry: ResMut<CodeEntry>,
+    mut chars: EventReader<ReceivedCharacter>,
+    keyboard: Res<Input<KeyCode>>,
+    mut text: Query<&mut Text, With<CodeEntryText>>,
+    nodes: Query<Entity, With<CodeEntryNode>>,
+) {
+    let modifier = modifier_pressed(&keyboard);
+    for ev in chars.iter() {
+        // Ctrl or Cmd with a key is a shortcut, not typing
+        if !ev.char.is_control() && !modifier {
+            entry.code.push(ev.char);
+            entry.error = None;
+        }
+    }
+
+    if modifier && keyboard.just_pressed(KeyCode::V) {
+        match paste_from_clipboard() {
+            Ok(text) => {
+                entry.code.push_str(&text);
+                entry.error = None;
+            }
+            Err(err) => entry.error = Some(err),
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::Back) {
+        entry.code.pop();
+        entry.error = None;
+    }
+
+    let close = |commands: &mut Commands| {
+        for ent in nodes.iter() {
+            commands.entity(ent).despawn_recursive();
+        }
+        commands.remove_resource::<CodeEntry>();
+    };
+
+    if keyboard.just_pressed(KeyCode::Escape) {
+        close(&mut commands);
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Return) && !entry.code.trim().is_empty() {
+        match decode_level(&entry.code) {
+            Ok(level) => {
+                close(&mut commands);
+                commands.insert_resource(SharedLevel(level));
+                commands.insert_resource(Run::new(GameMode::Campaign, None));
+                commands.insert_resource(NextState(AppState::InGame));
+                return;
+            }
+            Err(err) => entry.error = Some(err),
+        }
+    }
+
+    if entry.is_changed() {
+        let count = entry.code.chars().count();
+        let shown: String = entry
+            .code
+            .chars()
+            .skip(count.saturating_sub(SHOWN_CHARS))
+            .collect();
+        let ellipsis = if count > SHOWN_CHARS { "..." } else { "" };
+        for mut text in text.iter_mut() {
+            text.sections[0].value = match &entry.error {
+                Some(err) => err.clone(),
+                None => format!("{}{}_", ellipsis, shown),
+            };
+        }
+    }
+}
+
+/// Copies the code of the level being played with Ctrl+C (Cmd+C on a Mac)
+fn copy_level_code(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    level: Option<Res<CurrentLevel>>,
+    asset_server: Res<AssetServer>,
+    mut copied: Query<&mut Text, With<CopiedText>>,
+) {
+    if !(modifier_pressed(&keyboard) && keyboard.just_pressed(KeyCode::C)) {
+        return;
+    }
+    let level = match level {
+        Some(level) => level,
+        None => return,
+    };
+
+    let message = match copy_to_clipboard(&encode_level(&level.0)) {
+        Ok(()) => "Level code copied".to_string(),
+        Err(err) => err,
+    };
+    if let Ok(mut text) = copied.get_single_mut() {
+        text.sections[0].value = message;
+        return;
+    }
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(40.),
+                    right: Val::Px(15.),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::from_section(
+                message,
+                TextStyle {
+                    font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                    font_size: 24.0,
+                    color: Color::BLACK,
+                },
+            ),
+            ..default()
+        })
+        .insert(LevelNode)
+        .insert(CopiedText);
+}
+
+fn cancel_code_entry(mut commands: Commands, nodes: Query<Entity, With<CodeEntryNode>>) {
+    for ent in nodes.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+    commands.remove_resource::<CodeEntry>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_levels::{TargetData, TowerData};
+
+    fn level() -> LevelData {
+        LevelData {
+            tower: vec![TowerData {
+                kind: "wood".to_string(),
+                x: 0.,
+                y: 0.,
+                h: 40.,
+                w: 20.,
+                rotation: 15.,
+            }],
+            targets: vec![TargetData {
+                x: 0.,
+                y: 60.,
+                path: None,
+            }],
+            ..default()
+        }
+    }
+
+    #[test]
+    fn codes_read_back() {
+        let code = encode_level(&level());
+        assert!(code.starts_with(&format!("{}{}-", LEVEL_CODE_PREFIX, LEVEL_CODE_VERSION)));
+
+        // wrapped over lines by a chat window
+        let (start, end) = code.split_at(20);
+        let decoded = decode_level(&format!("{}\n  {}", start, end)).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(level()).unwrap()
+        );
+    }
+
+    #[test]
+    fn corrupt_codes_are_refused() {
+        let code = encode_level(&level());
+
+        assert!(decode_level("hello")
+            .unwrap_err()
+            .starts_with("not a level code"));
+        assert!(decode_level(&code[..code.len() / 2])
+            .unwrap_err()
+            .contains("damaged"));
+        assert!(decode_level(&format!("{}!", code))
+            .unwrap_err()
+            .contains("not base64"));
+    }
+
+    #[test]
+    fn wrong_checksums_are_refused() {
+        let code = encode_level(&level());
+        let (head, data) = code.split_once('-').unwrap();
+        let mut bytes = base64::decode_config(data, base64::URL_SAFE_NO_PAD).unwrap();
+        bytes[0] ^= 0xff;
+        let tampered = format!(
+            "{}-{}",
+            head,
+            base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+        );
+
+        assert!(decode_level(&tampered)
+            .unwrap_err()
+            .contains("checksum doesn't match"));
+    }
+
+    #[test]
+    fn newer_codes_are_refused() {
+        let code = encode_level(&level());
+        let newer = code.replacen(
+            &format!("{}{}-", LEVEL_CODE_PREFIX, LEVEL_CODE_VERSION),
+            &format!("{}{}-", LEVEL_CODE_PREFIX, LEVEL_CODE_VERSION + 1),
+            1,
+        );
+
+        assert!(decode_level(&newer).unwrap_err().contains("newer version"));
+    }
+}
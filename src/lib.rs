@@ -0,0 +1,148 @@
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
+
+//! Trash the Tower as a set of Bevy plugins. [`TrashTheTowerPlugins`] adds
+//! the whole game to an app that already has Bevy's `DefaultPlugins`; the
+//! plugins can also be added one by one to embed only part of it.
+
+use bevy::{app::PluginGroupBuilder, prelude::*, render::camera::ScalingMode};
+use bevy_prototype_lyon::prelude as lyon;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::{AppLooplessStateExt, CurrentState};
+
+pub mod actions;
+pub mod capture;
+pub mod cli;
+pub mod constants;
+pub mod debug;
+pub mod editor;
+pub mod environment;
+pub mod game;
+pub mod game_over;
+pub mod generator;
+pub mod headless;
+pub mod joints;
+pub mod leaderboard;
+pub mod level_code;
+pub mod main_menu;
+pub mod movers;
+pub mod particles;
+pub mod read_levels;
+pub mod replay;
+pub mod settings;
+pub mod shot_log;
+pub mod slowmo;
+pub mod solver;
+pub mod sound;
+pub mod terrain;
+pub mod undo;
+
+pub use actions::ActionsPlugin;
+pub use debug::DebugPlugin;
+pub use editor::EditorPlugin;
+pub use environment::EnvironmentPlugin;
+pub use game::{
+    BlockDestroyed, GameCamera, GamePlugin, LaunchEvent, LevelCompleted, LevelFailed, LevelStarted,
+    ShotEnded, ShotFired, TargetDestroyed,
+};
+pub use game_over::GameOverPlugin;
+pub use joints::JointsPlugin;
+pub use leaderboard::LeaderboardPlugin;
+pub use level_code::{decode_level, encode_level, LevelCodePlugin};
+pub use main_menu::MainMenuPlugin;
+pub use movers::MoversPlugin;
+pub use particles::ParticlesPlugin;
+pub use read_levels::{read_levels, LevelData};
+pub use replay::ReplayPlugin;
+pub use settings::SettingsPlugin;
+pub use shot_log::ShotLogPlugin;
+pub use slowmo::SlowMoPlugin;
+pub use sound::SoundPlugin;
+pub use undo::UndoPlugin;
+
+use cli::{Mode, Options};
+use constants::{HEIGHT, PIXELS_PER_METER, WIDTH};
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum AppState {
+    MainMenu,
+    InGame,
+    GameOver,
+    Leaderboard,
+    Settings,
+    Editor,
+}
+
+/// The app state. The game starts at the main menu unless the [`Options`]
+/// resource asks for a level, or the app already added the state itself
+pub struct StatePlugin;
+
+impl Plugin for StatePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Options>();
+        if app.world.contains_resource::<CurrentState<AppState>>() {
+            return;
+        }
+        let options = app.world.resource::<Options>();
+        let start = match (options.mode, options.level) {
+            (Mode::Editor, _) => AppState::Editor,
+            (_, Some(_)) => AppState::InGame,
+            (_, None) => AppState::MainMenu,
+        };
+        app.add_loopless_state(start);
+    }
+}
+
+/// A 2D camera showing the whole play field. Leave it out to draw the game
+/// with a camera of the app's own, tagged [`GameCamera`]
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_camera);
+    }
+}
+
+/// Everything the game needs on top of `DefaultPlugins`, physics and
+/// shape rendering included
+pub struct TrashTheTowerPlugins;
+
+impl PluginGroup for TrashTheTowerPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        // the state has to exist before the plugins add systems to it
+        group
+            .add(StatePlugin)
+            .add(CameraPlugin)
+            .add(lyon::ShapePlugin)
+            .add(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(
+                PIXELS_PER_METER,
+            ))
+            .add(ActionsPlugin)
+            .add(MainMenuPlugin)
+            .add(GamePlugin)
+            .add(GameOverPlugin)
+            .add(LeaderboardPlugin)
+            .add(LevelCodePlugin)
+            .add(SettingsPlugin)
+            .add(SoundPlugin)
+            .add(ParticlesPlugin)
+            .add(EnvironmentPlugin)
+            .add(MoversPlugin)
+            .add(JointsPlugin)
+            .add(SlowMoPlugin)
+            .add(ReplayPlugin)
+            .add(UndoPlugin)
+            .add(EditorPlugin)
+            .add(ShotLogPlugin)
+            .add(DebugPlugin);
+    }
+}
+
+fn setup_camera(mut commands: Commands) {
+    // keep the whole play field in view whatever the window size
+    let mut camera = Camera2dBundle::default();
+    camera.projection.scaling_mode = ScalingMode::Auto {
+        min_width: WIDTH,
+        min_height: HEIGHT,
+    };
+    commands.spawn_bundle(camera).insert(GameCamera);
+}
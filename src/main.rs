@@ -1,51 +1,137 @@
 use bevy::{prelude::*, window::WindowResizeConstraints};
-use bevy_prototype_lyon::prelude as lyon;
-use bevy_rapier2d::prelude::*;
-use iyes_loopless::prelude::AppLooplessStateExt;
 
-mod main_menu;
-use main_menu::MainMenuPlugin;
+use trash_the_tower::cli::{Mode, Options, USAGE};
+use trash_the_tower::game::{Levels, SharedLevel};
+use trash_the_tower::headless::settle_level;
+use trash_the_tower::read_levels::load_levels;
+use trash_the_tower::shot_log::{Playback, ShotLog, ShotRecorder};
+use trash_the_tower::{
+    decode_level, encode_level, generator, solver, LevelData, TrashTheTowerPlugins,
+};
 
-mod game;
-use game::GamePlugin;
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
 
-mod constants;
-use constants::{WIDTH, HEIGHT};
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
 
-mod read_levels;
+    let mut options = match Options::parse(&args[1..]) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-enum AppState {
-    MainMenu,
-    InGame
-}
-fn main() {
-    App::new()
-        .insert_resource(ClearColor(Color::rgb(0.16, 0.62, 0.76)))
+    if let Mode::Generate { seed, count } = options.mode {
+        generator::print_levels(seed, count);
+        return;
+    }
+
+    let levels = match load_levels(&options.levels) {
+        Ok(levels) => levels,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let mut replay = None;
+    match options.mode {
+        Mode::Play | Mode::Editor | Mode::Generate { .. } => {}
+        Mode::Replay => {
+            let path = options.shots.take().unwrap();
+            let log = match ShotLog::load(&path) {
+                Ok(log) => log,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(2);
+                }
+            };
+            options.level = Some(0);
+            replay = Some(log);
+        }
+        Mode::Solve => {
+            solver::solve_levels(&levels);
+            return;
+        }
+        Mode::Verify => {
+            let ok = solver::verify_levels(&levels);
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+        Mode::Settle => {
+            let levels: Vec<LevelData> = levels
+                .iter()
+                .enumerate()
+                .map(|(i, level)| {
+                    let settled = settle_level(level);
+                    if !settled.rested {
+                        eprintln!("level {}: still moving", i);
+                    }
+                    if settled.collapsed {
+                        eprintln!("level {}: collapses on its own", i);
+                    }
+                    if settled.targets_lost > 0 {
+                        eprintln!("level {}: loses {} target(s)", i, settled.targets_lost);
+                    }
+                    settled.level
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&levels).unwrap());
+            return;
+        }
+        Mode::Share => {
+            for level in &levels {
+                println!("{}", encode_level(level));
+            }
+            return;
+        }
+    }
+
+    let shared = match options.code.take().map(|code| decode_level(&code)) {
+        Some(Ok(level)) => {
+            options.level = Some(0);
+            Some(SharedLevel(level))
+        }
+        Some(Err(err)) => {
+            eprintln!("can't play that code: {}", err);
+            std::process::exit(2);
+        }
+        None => replay.as_ref().map(|log| SharedLevel(log.level.clone())),
+    };
+
+    let recorder = options.shots.clone().map(ShotRecorder::new);
+    let (width, height) = (options.width, options.height);
+
+    let mut app = App::new();
+    app.insert_resource(ClearColor(Color::rgb(0.16, 0.62, 0.76)))
         .insert_resource(WindowDescriptor {
             title: "Trash the Tower".to_string(),
-            width: WIDTH,
-            height: HEIGHT,
+            width,
+            height,
             resizable: false,
             resize_constraints: WindowResizeConstraints {
-                min_width: WIDTH,
-                max_width: WIDTH,
-                min_height: HEIGHT,
-                max_height: HEIGHT,
+                min_width: width,
+                max_width: width,
+                min_height: height,
+                max_height: height,
             },
             ..default()
         })
-        .add_loopless_state(AppState::MainMenu)
-        .add_startup_system(setup_camera)
+        .insert_resource(options)
+        .insert_resource(Levels(levels))
         .add_plugins(DefaultPlugins)
-        .add_plugin(lyon::ShapePlugin)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(30.0))
-        .add_plugin(MainMenuPlugin)
-        .add_plugin(GamePlugin)
-        // .add_plugin(RapierDebugRenderPlugin::default())
-        .run();
-}
-
-fn setup_camera(mut commands: Commands) {
-    commands.spawn_bundle(Camera2dBundle::default());
+        .add_plugins(TrashTheTowerPlugins);
+    if let Some(shared) = shared {
+        app.insert_resource(shared);
+    }
+    if let Some(log) = replay {
+        app.insert_resource(Playback::new(log.shots));
+    }
+    if let Some(recorder) = recorder {
+        app.insert_resource(recorder);
+    }
+    app.run();
 }
@@ -1,9 +1,17 @@
 use bevy::app::AppExit;
+use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
+use bevy::ui::UiSystem;
 use iyes_loopless::prelude::*;
 
 use super::AppState;
+use crate::actions::gamepad_just_pressed;
+use crate::cli::Options;
 use crate::constants::{HOVERED_BUTTON, NORMAL_BUTTON, PRESSED_BUTTON};
+use crate::game::{GameMode, Run};
+use crate::leaderboard::NameEntry;
+use crate::level_code::{start_code_entry, CodeEntry};
+use crate::sound::{PlaySound, Sound};
 
 #[derive(Component)]
 struct MenuNode;
@@ -11,6 +19,21 @@ struct MenuNode;
 #[derive(Component)]
 struct GameStartBtn;
 
+#[derive(Component)]
+struct EndlessBtn;
+
+#[derive(Component)]
+struct CodeBtn;
+
+#[derive(Component)]
+struct EditorBtn;
+
+#[derive(Component)]
+struct LeaderboardBtn;
+
+#[derive(Component)]
+struct SettingsBtn;
+
 #[derive(Component)]
 struct QuitBtn;
 
@@ -23,10 +46,22 @@ impl Plugin for MainMenuPlugin {
             .add_system_set(
                 ConditionSet::new()
                     .run_in_state(AppState::MainMenu)
+                    .run_unless_resource_exists::<CodeEntry>()
                     .with_system(btn_start_game.run_if(on_btn_interact::<GameStartBtn>))
+                    .with_system(btn_start_endless.run_if(on_btn_interact::<EndlessBtn>))
+                    .with_system(btn_play_code.run_if(on_btn_interact::<CodeBtn>))
+                    .with_system(btn_editor.run_if(on_btn_interact::<EditorBtn>))
+                    .with_system(btn_leaderboard.run_if(on_btn_interact::<LeaderboardBtn>))
+                    .with_system(btn_settings.run_if(on_btn_interact::<SettingsBtn>))
                     .with_system(btn_quit_game.run_if(on_btn_interact::<QuitBtn>))
                     .with_system(button_system)
                     .into(),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                menu_navigation
+                    .run_unless_resource_exists::<NameEntry>()
+                    .after(UiSystem::Focus),
             );
     }
 }
@@ -71,74 +106,107 @@ fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
                         .insert(MenuNode);
                 });
 
-            container
-                .spawn_bundle(ButtonBundle {
-                    style: Style {
-                        size: Size::new(Val::Px(225.0), Val::Px(65.0)),
-                        margin: UiRect::all(Val::Px(10.)),
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::Center,
-                        ..default()
-                    },
-                    color: NORMAL_BUTTON.into(),
-                    ..default()
-                })
-                .with_children(|parent| {
-                    parent.spawn_bundle(TextBundle {
-                        text: Text::from_section(
-                            "Start Game",
-                            TextStyle {
-                                font: asset_server.load("fonts/JetBrainsMono-Regular.ttf"),
-                                font_size: 40.0,
-                                color: Color::rgb(0.9, 0.9, 0.9),
-                            },
-                        ),
-                        ..default()
-                    });
-                })
+            menu_button(container, &asset_server, "Start Game")
                 .insert(MenuNode)
                 .insert(GameStartBtn);
 
+            // side by side to leave room for the rest
             container
-                .spawn_bundle(ButtonBundle {
+                .spawn_bundle(NodeBundle {
                     style: Style {
-                        size: Size::new(Val::Px(225.0), Val::Px(65.0)),
-                        margin: UiRect::all(Val::Px(10.)),
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::Center,
+                        flex_direction: FlexDirection::Row,
                         ..default()
                     },
-                    color: NORMAL_BUTTON.into(),
+                    color: Color::NONE.into(),
                     ..default()
                 })
-                .with_children(|parent| {
-                    parent.spawn_bundle(TextBundle {
-                        text: Text::from_section(
-                            "Quit",
-                            TextStyle {
-                                font: asset_server.load("fonts/JetBrainsMono-Regular.ttf"),
-                                font_size: 40.0,
-                                color: Color::rgb(0.9, 0.9, 0.9),
-                            },
-                        ),
-                        ..default()
-                    });
-                })
+                .insert(MenuNode)
+                .with_children(|row| {
+                    menu_button(row, &asset_server, "Endless").insert(EndlessBtn);
+                    menu_button(row, &asset_server, "Play Code").insert(CodeBtn);
+                    menu_button(row, &asset_server, "Editor").insert(EditorBtn);
+                });
+
+            menu_button(container, &asset_server, "High Scores")
+                .insert(MenuNode)
+                .insert(LeaderboardBtn);
+
+            menu_button(container, &asset_server, "Settings")
+                .insert(MenuNode)
+                .insert(SettingsBtn);
+
+            menu_button(container, &asset_server, "Quit")
                 .insert(MenuNode)
                 .insert(QuitBtn);
         });
 }
 
+pub fn menu_button<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    asset_server: &AssetServer,
+    label: &str,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut button = parent.spawn_bundle(ButtonBundle {
+        style: Style {
+            size: Size::new(Val::Px(225.0), Val::Px(65.0)),
+            margin: UiRect::all(Val::Px(10.)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        color: NORMAL_BUTTON.into(),
+        ..default()
+    });
+
+    button.with_children(|parent| {
+        parent.spawn_bundle(TextBundle {
+            text: Text::from_section(
+                label,
+                TextStyle {
+                    font: asset_server.load("fonts/JetBrainsMono-Regular.ttf"),
+                    font_size: 40.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                },
+            ),
+            ..default()
+        });
+    });
+
+    button
+}
+
 fn remove_menu(mut commands: Commands, query: Query<Entity, With<MenuNode>>) {
     for ent in query.iter() {
         commands.entity(ent).despawn_recursive();
     }
 }
 
-fn btn_start_game(mut commands: Commands) {
+fn btn_start_game(mut commands: Commands, options: Res<Options>) {
+    commands.insert_resource(Run::new(GameMode::Campaign, options.seed));
     commands.insert_resource(NextState(AppState::InGame));
 }
 
+fn btn_start_endless(mut commands: Commands, options: Res<Options>) {
+    commands.insert_resource(Run::new(GameMode::Endless, options.seed));
+    commands.insert_resource(NextState(AppState::InGame));
+}
+
+fn btn_play_code(mut commands: Commands, asset_server: Res<AssetServer>) {
+    start_code_entry(&mut commands, &asset_server);
+}
+
+fn btn_editor(mut commands: Commands) {
+    commands.insert_resource(NextState(AppState::Editor));
+}
+
+fn btn_leaderboard(mut commands: Commands) {
+    commands.insert_resource(NextState(AppState::Leaderboard));
+}
+
+fn btn_settings(mut commands: Commands) {
+    commands.insert_resource(NextState(AppState::Settings));
+}
+
 fn btn_quit_game(mut exit: EventWriter<AppExit>) {
     exit.send(AppExit);
 }
@@ -157,15 +225,99 @@ pub fn on_btn_interact<B: Component>(
 
 pub fn button_system(
     mut interaction_query: Query<
-        (&Interaction, &mut UiColor),
+        (Entity, &Interaction, &mut UiColor),
         (Changed<Interaction>, With<Button>),
     >,
+    mut sounds: EventWriter<PlaySound>,
+    mut hovered: Local<Option<Entity>>,
 ) {
-    for (interaction, mut color) in interaction_query.iter_mut() {
+    for (ent, interaction, mut color) in interaction_query.iter_mut() {
         match *interaction {
-            Interaction::Clicked => *color = PRESSED_BUTTON.into(),
-            Interaction::Hovered => *color = HOVERED_BUTTON.into(),
-            Interaction::None => *color = NORMAL_BUTTON.into(),
+            Interaction::Clicked => {
+                *color = PRESSED_BUTTON.into();
+                sounds.send(PlaySound(Sound::ButtonClick));
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+                // the keyboard highlight is reapplied every frame, only
+                // play the sound when the highlight moves
+                if *hovered != Some(ent) {
+                    *hovered = Some(ent);
+                    sounds.send(PlaySound(Sound::ButtonHover));
+                }
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+                if *hovered == Some(ent) {
+                    *hovered = None;
+                }
+            }
+        }
+    }
+}
+
+/// Moves a highlight through the buttons on screen with the arrow keys or
+/// the d-pad, and clicks the highlighted one with enter or the south button,
+/// so every menu works without a mouse
+fn menu_navigation(
+    keyboard: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<Input<GamepadButton>>,
+    mut cursor_moved: EventReader<CursorMoved>,
+    mut buttons: Query<(Entity, &GlobalTransform, &mut Interaction), With<Button>>,
+    mut focus: Local<Option<Entity>>,
+    mut clicked: Local<Option<Entity>>,
+) {
+    // a click only lasts one frame
+    if let Some(ent) = clicked.take() {
+        if let Ok((_, _, mut interaction)) = buttons.get_mut(ent) {
+            *interaction = Interaction::None;
+        }
+    }
+
+    let pressed =
+        |key, pad| keyboard.just_pressed(key) || gamepad_just_pressed(&gamepads, &pad_buttons, pad);
+    let step = if pressed(KeyCode::Down, GamepadButtonType::DPadDown) {
+        1
+    } else if pressed(KeyCode::Up, GamepadButtonType::DPadUp) {
+        -1
+    } else {
+        0
+    };
+
+    // top to bottom, then left to right
+    let mut order: Vec<(Entity, Vec3)> = buttons
+        .iter()
+        .map(|(ent, trans, _)| (ent, trans.translation()))
+        .collect();
+    order.sort_by(|(_, a), (_, b)| b.y.total_cmp(&a.y).then(a.x.total_cmp(&b.x)));
+
+    let current = order.iter().position(|(ent, _)| Some(*ent) == *focus);
+    if current.is_none() {
+        *focus = None;
+    }
+
+    if cursor_moved.iter().next().is_some() {
+        *focus = None;
+        return;
+    }
+
+    if step != 0 && !order.is_empty() {
+        let next = current.map_or(0, |i| (i as i32 + step).rem_euclid(order.len() as i32));
+        if let Some((_, _, mut interaction)) =
+            current.and_then(|i| buttons.get_mut(order[i].0).ok())
+        {
+            *interaction = Interaction::None;
+        }
+        *focus = Some(order[next as usize].0);
+    }
+
+    if let Some((_, _, mut interaction)) = focus.and_then(|ent| buttons.get_mut(ent).ok()) {
+        if pressed(KeyCode::Return, GamepadButtonType::South) {
+            *interaction = Interaction::Clicked;
+            *clicked = *focus;
+        } else if *interaction == Interaction::None {
+            *interaction = Interaction::Hovered;
         }
     }
 }
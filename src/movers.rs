@@ -0,0 +1,175 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::environment::Environment;
+use crate::game::Paused;
+use crate::read_levels::{PathData, PendulumData};
+
+/// A kinematic body following a path, spinning or swinging, timed by the
+/// level clock so every restart plays out the same
+#[derive(Component)]
+pub struct Mover {
+    /// Where the body starts, or the pivot of a pendulum
+    origin: Vec2,
+    /// Closed loop of world positions, starting at `origin`
+    path: Vec<Vec2>,
+    speed: f32,
+    /// Radians per second
+    angular_speed: f32,
+    pendulum: Option<PendulumData>,
+}
+
+impl Mover {
+    pub fn new(
+        origin: Vec2,
+        path: Option<&PathData>,
+        angular_speed: f32,
+        pendulum: Option<PendulumData>,
+    ) -> Self {
+        let (points, speed) = match path {
+            Some(path) => (
+                std::iter::once(origin)
+                    .chain(path.points.iter().map(|p| origin + Vec2::new(p.x, p.y)))
+                    .collect(),
+                path.speed,
+            ),
+            None => (vec![origin], 0.),
+        };
+
+        Mover {
+            origin,
+            path: points,
+            speed,
+            angular_speed: angular_speed.to_radians(),
+            pendulum,
+        }
+    }
+
+    /// Position and rotation `t` seconds into the level
+    pub fn pose(&self, t: f32) -> (Vec2, f32) {
+        if let Some(pendulum) = &self.pendulum {
+            let angle =
+                pendulum.amplitude.to_radians() * (t * TAU / pendulum.period.max(0.1)).sin();
+            let pos = self.origin + Vec2::new(angle.sin(), -angle.cos()) * pendulum.length;
+            return (pos, angle);
+        }
+
+        (self.along_path(t), self.angular_speed * t)
+    }
+
+    fn along_path(&self, t: f32) -> Vec2 {
+        let segments = || {
+            self.path
+                .iter()
+                .zip(self.path.iter().cycle().skip(1))
+                .map(|(a, b)| (*a, *b))
+        };
+        let length: f32 = segments().map(|(a, b)| a.distance(b)).sum();
+        if length <= 0. || self.speed <= 0. {
+            return self.origin;
+        }
+
+        let mut dist = (self.speed * t).rem_euclid(length);
+        for (a, b) in segments() {
+            let seg = a.distance(b);
+            if dist <= seg {
+                return a.lerp(b, dist / seg.max(f32::EPSILON));
+            }
+            dist -= seg;
+        }
+        self.origin
+    }
+}
+
+pub struct MoversPlugin;
+
+impl Plugin for MoversPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            move_kinematic
+                .run_in_state(AppState::InGame)
+                .run_unless_resource_exists::<Paused>(),
+        );
+    }
+}
+
+/// Puts every mover where the level clock says it should be. Rapier moves
+/// kinematic bodies there smoothly, pushing whatever is in the way
+pub fn move_kinematic(env: Res<Environment>, mut movers: Query<(&Mover, &mut Transform)>) {
+    for (mover, mut trans) in movers.iter_mut() {
+        let (pos, angle) = mover.pose(env.time);
+        trans.translation = pos.extend(trans.translation.z);
+        trans.rotation = Quat::from_rotation_z(angle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_levels::PointData;
+
+    fn close(a: Vec2, b: Vec2) -> bool {
+        a.distance(b) < 1e-3
+    }
+
+    #[test]
+    fn paths_loop_back_to_the_start() {
+        let path = PathData {
+            points: vec![PointData { x: 100., y: 0. }, PointData { x: 100., y: 50. }],
+            speed: 50.,
+        };
+        let origin = Vec2::new(10., 20.);
+        let mover = Mover::new(origin, Some(&path), 0., None);
+
+        // 100 out, 50 up, then about 112 straight back
+        assert!(close(mover.along_path(0.), origin));
+        assert!(close(mover.along_path(1.), origin + Vec2::new(50., 0.)));
+        assert!(close(mover.along_path(2.5), origin + Vec2::new(100., 25.)));
+        let length = 150. + Vec2::new(100., 50.).length();
+        assert!(close(mover.along_path(length / 50.), origin));
+        assert!(close(
+            mover.along_path(length / 50. + 1.),
+            origin + Vec2::new(50., 0.)
+        ));
+    }
+
+    #[test]
+    fn movers_without_a_path_stay_put() {
+        let origin = Vec2::new(5., 5.);
+        let spinner = Mover::new(origin, None, 90., None);
+        let (pos, angle) = spinner.pose(2.);
+        assert!(close(pos, origin));
+        assert!((angle - std::f32::consts::PI).abs() < 1e-5);
+
+        let stalled = PathData {
+            points: vec![PointData { x: 100., y: 0. }],
+            speed: 0.,
+        };
+        let mover = Mover::new(origin, Some(&stalled), 0., None);
+        assert!(close(mover.pose(3.).0, origin));
+    }
+
+    #[test]
+    fn pendulums_swing_below_their_pivot() {
+        let pivot = Vec2::new(0., 100.);
+        let pendulum = PendulumData {
+            length: 50.,
+            amplitude: 30.,
+            period: 2.,
+        };
+        let mover = Mover::new(pivot, None, 0., Some(pendulum));
+
+        let (pos, angle) = mover.pose(0.);
+        assert!(close(pos, Vec2::new(0., 50.)));
+        assert_eq!(angle, 0.);
+
+        // a quarter period out is the widest swing
+        let (pos, angle) = mover.pose(0.5);
+        assert!((angle - 30f32.to_radians()).abs() < 1e-5);
+        assert!((pos.distance(pivot) - 50.).abs() < 1e-3);
+        assert!(pos.x > 0.);
+    }
+}
@@ -0,0 +1,249 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude as lyon;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+use rand::Rng;
+
+use super::AppState;
+use crate::constants::{
+    DEBRIS_FORCE, DUST_FORCE, GROUND_COLOR, MAX_PARTICLES, PARTICLE_GRAVITY, TARGET_COLOR,
+};
+use crate::game::{BlockMaterial, Paused, TargetDestroyed};
+use crate::settings::Settings;
+use crate::terrain::{Ground, Terrain};
+
+/// A square of debris, dust or smoke. Every particle lives in a fixed pool
+/// and is only hidden when it dies, so effects never spawn entities
+#[derive(Component, Default)]
+struct Particle {
+    velocity: Vec2,
+    spin: f32,
+    /// Fraction of `PARTICLE_GRAVITY` pulling on it, negative floats up
+    gravity: f32,
+    /// Fraction of the velocity lost per second
+    drag: f32,
+    life: f32,
+    lifetime: f32,
+    size: f32,
+}
+
+/// What a newly emitted particle looks like and how it moves
+struct Emit {
+    position: Vec2,
+    velocity: Vec2,
+    color: Color,
+    size: f32,
+    lifetime: f32,
+    gravity: f32,
+    drag: f32,
+}
+
+/// Index of the next pool entry to reuse once every particle is alive
+#[derive(Default)]
+struct NextParticle(usize);
+
+pub struct ParticlesPlugin;
+
+impl Plugin for ParticlesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NextParticle>()
+            .add_startup_system(spawn_pool)
+            .add_exit_system(AppState::InGame, clear_particles)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::InGame)
+                    .run_unless_resource_exists::<Paused>()
+                    .with_system(emit_particles)
+                    .with_system(update_particles)
+                    .into(),
+            );
+    }
+}
+
+fn spawn_pool(mut commands: Commands) {
+    for _ in 0..MAX_PARTICLES {
+        commands
+            .spawn_bundle(lyon::GeometryBuilder::build_as(
+                &lyon::shapes::Rectangle {
+                    extents: Vec2::ONE,
+                    origin: lyon::shapes::RectangleOrigin::Center,
+                },
+                lyon::DrawMode::Fill(lyon::FillMode::color(Color::WHITE)),
+                Transform::default(),
+            ))
+            .insert(Visibility { is_visible: false })
+            .insert(Particle::default());
+    }
+}
+
+fn emit_particles(
+    settings: Res<Settings>,
+    mut next: ResMut<NextParticle>,
+    mut destroyed_events: EventReader<TargetDestroyed>,
+    mut contact_force_events: EventReader<ContactForceEvent>,
+    terrain: Res<Terrain>,
+    ground: Query<Entity, With<Ground>>,
+    bodies: Query<&Transform, (With<RigidBody>, Without<Particle>)>,
+    blocks: Query<(&Transform, &BlockMaterial), Without<Particle>>,
+    mut particles: Query<(
+        &mut Particle,
+        &mut Transform,
+        &mut Visibility,
+        &mut lyon::DrawMode,
+    )>,
+) {
+    let mut rng = rand::thread_rng();
+    let mut emits = Vec::new();
+
+    let contacts: Vec<&ContactForceEvent> = contact_force_events.iter().collect();
+
+    // hard hits knock chips off the blocks, the harder the more
+    for ev in &contacts {
+        if ev.total_force_magnitude < DEBRIS_FORCE {
+            continue;
+        }
+        let count = (ev.total_force_magnitude / DEBRIS_FORCE).min(4.) as usize * 2;
+        for ent in [ev.collider1, ev.collider2] {
+            let (trans, material) = match blocks.get(ent) {
+                Ok(block) => block,
+                Err(_) => continue,
+            };
+            for i in 0..count {
+                let color = if i % 2 == 0 {
+                    material.0.color1
+                } else {
+                    material.0.color2
+                };
+                emits.push(Emit {
+                    position: trans.translation.truncate(),
+                    velocity: Vec2::new(rng.gen_range(-150.0..150.0), rng.gen_range(0.0..250.0)),
+                    color,
+                    size: rng.gen_range(3.0..7.0),
+                    lifetime: rng.gen_range(0.6..1.0),
+                    gravity: 1.,
+                    drag: 0.2,
+                });
+            }
+        }
+    }
+
+    for destroyed in destroyed_events.iter() {
+        for i in 0..12 {
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            let color = if i % 2 == 0 {
+                TARGET_COLOR.0
+            } else {
+                Color::rgba(0.9, 0.9, 0.9, 0.8)
+            };
+            emits.push(Emit {
+                position: destroyed.position,
+                velocity: Vec2::from_angle(angle) * rng.gen_range(60.0..160.0),
+                color,
+                size: rng.gen_range(5.0..9.0),
+                lifetime: rng.gen_range(0.4..0.7),
+                gravity: -0.1,
+                drag: 3.,
+            });
+        }
+    }
+
+    if !ground.is_empty() {
+        for ev in contacts {
+            if ev.total_force_magnitude < DUST_FORCE
+                || !(ground.contains(ev.collider1) || ground.contains(ev.collider2))
+            {
+                continue;
+            }
+
+            // the event doesn't say where the bodies touch, so the dust
+            // rises under whatever hit the ground
+            let other = if ground.contains(ev.collider1) {
+                ev.collider2
+            } else {
+                ev.collider1
+            };
+            let x = bodies.get(other).map_or(0., |trans| trans.translation.x);
+            let ground_top = terrain.height_at(x);
+            let count = (ev.total_force_magnitude / DUST_FORCE).min(4.) as usize * 3;
+            for _ in 0..count {
+                emits.push(Emit {
+                    position: Vec2::new(x + rng.gen_range(-15.0..15.0), ground_top),
+                    velocity: Vec2::new(rng.gen_range(-80.0..80.0), rng.gen_range(10.0..50.0)),
+                    color: GROUND_COLOR * 1.3,
+                    size: rng.gen_range(3.0..7.0),
+                    lifetime: rng.gen_range(0.4..0.8),
+                    gravity: 0.1,
+                    drag: 2.,
+                });
+            }
+        }
+    }
+
+    // the events are drained either way so turning particles back on
+    // doesn't replay old ones
+    if !settings.particles {
+        return;
+    }
+
+    let mut pool: Vec<_> = particles.iter_mut().collect();
+    for emit in emits.into_iter().take(MAX_PARTICLES) {
+        // prefer a dead particle, else recycle the oldest in turn
+        let idx = pool
+            .iter()
+            .position(|(p, ..)| p.life <= 0.)
+            .unwrap_or_else(|| {
+                next.0 = (next.0 + 1) % pool.len();
+                next.0
+            });
+        let (particle, trans, visibility, draw_mode) = &mut pool[idx];
+        **particle = Particle {
+            velocity: emit.velocity,
+            spin: rng.gen_range(-8.0..8.0),
+            gravity: emit.gravity,
+            drag: emit.drag,
+            life: emit.lifetime,
+            lifetime: emit.lifetime,
+            size: emit.size,
+        };
+        **trans = Transform::from_translation(emit.position.extend(5.))
+            .with_scale(Vec3::splat(emit.size));
+        visibility.is_visible = true;
+        **draw_mode = lyon::DrawMode::Fill(lyon::FillMode::color(emit.color));
+    }
+}
+
+fn update_particles(
+    time: Res<Time>,
+    mut particles: Query<(&mut Particle, &mut Transform, &mut Visibility)>,
+) {
+    let dt = time.delta_seconds();
+    for (mut particle, mut trans, mut visibility) in particles.iter_mut() {
+        if particle.life <= 0. {
+            continue;
+        }
+
+        particle.life -= dt;
+        if particle.life <= 0. {
+            visibility.is_visible = false;
+            continue;
+        }
+
+        let gravity = PARTICLE_GRAVITY * particle.gravity;
+        let drag = (1. - particle.drag * dt).max(0.);
+        particle.velocity.y += gravity * dt;
+        particle.velocity *= drag;
+        trans.translation += (particle.velocity * dt).extend(0.);
+        trans.rotate_z(particle.spin * dt);
+
+        // shrink away over the last part of its life
+        let scale = particle.size * (particle.life / particle.lifetime).min(0.5) * 2.;
+        trans.scale = Vec3::splat(scale);
+    }
+}
+
+fn clear_particles(mut particles: Query<(&mut Particle, &mut Visibility)>) {
+    for (mut particle, mut visibility) in particles.iter_mut() {
+        particle.life = 0.;
+        visibility.is_visible = false;
+    }
+}
@@ -1,28 +1,590 @@
-use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-#[derive(Deserialize, Debug)]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::LEVELS_FILE;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TowerData {
     pub kind: String,
     pub x: f32,
     pub y: f32,
     pub h: f32,
     pub w: f32,
+    /// Degrees counter-clockwise about the centre of the block
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rotation: f32,
 }
 
-#[derive(Deserialize, Debug)]
+fn is_zero(value: &f32) -> bool {
+    *value == 0.
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TargetData {
     pub x: f32,
     pub y: f32,
+    /// Patrol route, the target doesn't fall or get knocked around while
+    /// it follows one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PointData {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Waypoints relative to the starting position, visited in order at
+/// `speed` pixels per second before heading back to the start
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PathData {
+    pub points: Vec<PointData>,
+    pub speed: f32,
+}
+
+/// Swinging back and forth below a pivot
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendulumData {
+    pub length: f32,
+    /// Widest swing either side, in degrees
+    pub amplitude: f32,
+    /// Seconds for a swing there and back
+    pub period: f32,
+}
+
+/// A block moved by the level itself rather than by hits: a platform on a
+/// path, an obstacle spinning at `angular_speed` degrees per second, or a
+/// pendulum hanging from `x`, `y`. Positioned by its centre
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MoverData {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathData>,
+    #[serde(default)]
+    pub angular_speed: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pendulum: Option<PendulumData>,
+}
+
+/// Wind blowing on the ball in flight and on light blocks, as an
+/// acceleration in pixels per second squared
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WindData {
+    pub x: f32,
+    pub y: f32,
+    /// How much the wind strength swings around its average, 0 for a
+    /// steady wind and 1 for gusts that die down completely
+    #[serde(default)]
+    pub gust: f32,
+    /// Seconds between the strongest gusts
+    #[serde(default = "default_gust_period")]
+    pub gust_period: f32,
+}
+
+fn default_gust_period() -> f32 {
+    3.0
+}
+
+/// An area with different gravity or air drag, placed like the tower
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ZoneData {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+    /// Multiplier of the normal gravity, negative pulls upwards
+    #[serde(default = "default_gravity")]
+    pub gravity: f32,
+    /// Linear damping of every body inside
+    #[serde(default)]
+    pub drag: f32,
+}
+
+fn default_gravity() -> f32 {
+    1.0
+}
+
+/// A tower block or target, by its index in the level
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyRef {
+    Tower(usize),
+    Target(usize),
+}
+
+/// Ties body `a` to body `b`, or to the world where `b` is left out.
+/// `kind` is `"glue"`, holding both rigidly together until pulled harder
+/// than `strength`, `"hinge"`, letting them turn about `x`, `y` within the
+/// optional `limits` in degrees, or `"rope"`, keeping the centre of `a` no
+/// further than `length` from `x`, `y` on `b`, or than it starts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JointData {
+    pub kind: String,
+    pub a: BodyRef,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub b: Option<BodyRef>,
+    pub x: f32,
+    pub y: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strength: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<f32>,
 }
 
-#[derive(Deserialize, Debug)]
+/// Edges of the world, measured from the foot of the tower
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BoundsData {
+    pub left: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub top: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct LevelData {
     pub tower: Vec<TowerData>,
-    pub targets: Vec<TargetData>
+    pub targets: Vec<TargetData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wind: Option<WindData>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<ZoneData>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub movers: Vec<MoverData>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub joints: Vec<JointData>,
+    /// Ground surface from left to right, placed like the tower but
+    /// measured up from the usual flat ground. Flat when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub terrain: Vec<PointData>,
+    /// Past the window by a margin when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<BoundsData>,
+}
+
+/// Checks a level can be played: it has something to knock down, every
+/// number is finite, no block is without a size, the terrain runs left to
+/// right, the bounds aren't inside out, everything that moves does and every
+/// joint is on a body that exists, with a rope of some length
+pub fn validate_level(level: &LevelData) -> Result<(), String> {
+    if level.targets.is_empty() {
+        return Err("the level has no targets".to_string());
+    }
+    for (i, block) in level.tower.iter().enumerate() {
+        check_finite(
+            &format!("block {}", i),
+            &[block.x, block.y, block.w, block.h, block.rotation],
+        )?;
+        if !(block.w > 0. && block.h > 0.) {
+            return Err(format!("block {} has no size", i));
+        }
+    }
+    for (i, target) in level.targets.iter().enumerate() {
+        let what = format!("target {}", i);
+        check_finite(&what, &[target.x, target.y])?;
+        if let Some(path) = &target.path {
+            check_path(&what, path)?;
+        }
+    }
+    if let Some(wind) = &level.wind {
+        check_finite("the wind", &[wind.x, wind.y, wind.gust, wind.gust_period])?;
+    }
+    for (i, zone) in level.zones.iter().enumerate() {
+        let what = format!("zone {}", i);
+        check_finite(
+            &what,
+            &[zone.x, zone.y, zone.w, zone.h, zone.gravity, zone.drag],
+        )?;
+    }
+    for (i, mover) in level.movers.iter().enumerate() {
+        let what = format!("mover {}", i);
+        check_finite(
+            &what,
+            &[mover.x, mover.y, mover.w, mover.h, mover.angular_speed],
+        )?;
+        if let Some(path) = &mover.path {
+            check_path(&what, path)?;
+        }
+        if let Some(pendulum) = &mover.pendulum {
+            check_finite(
+                &what,
+                &[pendulum.length, pendulum.amplitude, pendulum.period],
+            )?;
+            if pendulum.period <= 0. {
+                return Err(format!("{} swings with a period that isn't positive", what));
+            }
+        }
+    }
+    for (i, joint) in level.joints.iter().enumerate() {
+        let what = format!("joint {}", i);
+        check_finite(&what, &[joint.x, joint.y])?;
+        check_finite(&what, joint.strength.as_slice())?;
+        check_finite(
+            &what,
+            joint.limits.as_ref().map_or(&[], |limits| &limits[..]),
+        )?;
+        check_finite(&what, joint.length.as_slice())?;
+        if joint.length.is_some_and(|length| length <= 0.) {
+            return Err(format!("{} has a length that isn't positive", what));
+        }
+        for body in std::iter::once(joint.a).chain(joint.b) {
+            let exists = match body {
+                BodyRef::Tower(j) => j < level.tower.len(),
+                BodyRef::Target(j) => j < level.targets.len(),
+            };
+            if !exists {
+                return Err(format!("joint {} is on missing body {:?}", i, body));
+            }
+        }
+    }
+    for (i, point) in level.terrain.iter().enumerate() {
+        check_finite(&format!("terrain point {}", i), &[point.x, point.y])?;
+    }
+    if let Some(i) = level
+        .terrain
+        .windows(2)
+        .position(|pair| pair[0].x > pair[1].x)
+    {
+        return Err(format!(
+            "terrain point {} is left of the one before it",
+            i + 1
+        ));
+    }
+    if let Some(bounds) = &level.bounds {
+        check_finite(
+            "the bounds",
+            &[bounds.left, bounds.right, bounds.bottom, bounds.top],
+        )?;
+        if bounds.left >= bounds.right || bounds.bottom >= bounds.top {
+            return Err("the bounds are inside out".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn check_finite(what: &str, values: &[f32]) -> Result<(), String> {
+    if values.iter().all(|value| value.is_finite()) {
+        Ok(())
+    } else {
+        Err(format!("{} has a number that isn't finite", what))
+    }
+}
+
+fn check_path(what: &str, path: &PathData) -> Result<(), String> {
+    check_finite(what, &[path.speed])?;
+    for point in path.points.iter() {
+        check_finite(what, &[point.x, point.y])?;
+    }
+    if path.speed <= 0. {
+        return Err(format!(
+            "{} follows its path at a speed that isn't positive",
+            what
+        ));
+    }
+    Ok(())
 }
 
 pub fn read_levels() -> Vec<LevelData> {
-    let json = std::fs::read_to_string("./assets/levels.json").expect("");
-    let data: Vec<LevelData> = serde_json::from_str(&json).unwrap();
-    data
-}
\ No newline at end of file
+    read_levels_from(Path::new(LEVELS_FILE))
+}
+
+/// Levels from a file, panicking with the reason if it can't be used. See
+/// `load_levels`
+pub fn read_levels_from(path: &Path) -> Vec<LevelData> {
+    load_levels(path).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Levels from a list in a `.json` or `.ron` file, a `.toml` file of
+/// `[[level]]` tables or of one level, or a directory of such files holding
+/// one level each, played in file name order. Every level is checked with
+/// `validate_level`
+pub fn load_levels(path: &Path) -> Result<Vec<LevelData>, String> {
+    if !path.is_dir() {
+        // JSON unless the extension says otherwise, like it always was
+        let levels = match Format::of(path).unwrap_or(Format::Json) {
+            Format::Toml => parse_toml(path)?,
+            format => parse::<Vec<LevelData>>(path, format)?,
+        };
+        for (i, level) in levels.iter().enumerate() {
+            validate_level(level)
+                .map_err(|err| format!("{}, level {}: {}", path.display(), i, err))?;
+        }
+        if levels.is_empty() {
+            return Err(format!("{} has no levels", path.display()));
+        }
+        return Ok(levels);
+    }
+
+    let mut files: Vec<(PathBuf, Format)> = fs::read_dir(path)
+        .map_err(|err| format!("can't read {}: {}", path.display(), err))?
+        .filter_map(|entry| {
+            let file = entry.ok()?.path();
+            let format = Format::of(&file).filter(|_| file.is_file())?;
+            Some((file, format))
+        })
+        .collect();
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if files.is_empty() {
+        return Err(format!("{} has no level files", path.display()));
+    }
+
+    let mut levels = Vec::new();
+    for (file, format) in files.iter() {
+        let found = match format {
+            Format::Toml => parse_toml(file)?,
+            format => vec![parse::<LevelData>(file, *format)?],
+        };
+        for level in found {
+            validate_level(&level).map_err(|err| format!("{}: {}", file.display(), err))?;
+            levels.push(level);
+        }
+    }
+    Ok(levels)
+}
+
+/// How a levels file is written, by its extension
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    Ron,
+    Toml,
+}
+
+impl Format {
+    fn of(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" => Some(Format::Json),
+            "ron" => Some(Format::Ron),
+            "toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// A TOML file can't be a bare list, so its levels are `[[level]]` tables
+#[derive(Deserialize)]
+struct TomlLevels {
+    #[serde(default)]
+    level: Vec<LevelData>,
+}
+
+/// The `[[level]]` tables of a TOML file, or the one level written at the
+/// top of it, so a file reads the same on its own or in a directory
+fn parse_toml(path: &Path) -> Result<Vec<LevelData>, String> {
+    let value: toml::Value = parse(path, Format::Toml)?;
+    let levels = if value.get("level").is_some() {
+        value.try_into::<TomlLevels>().map(|file| file.level)
+    } else {
+        value.try_into::<LevelData>().map(|level| vec![level])
+    };
+    levels.map_err(|err| format!("can't parse {}: {}", path.display(), err))
+}
+
+fn parse<T: DeserializeOwned>(path: &Path, format: Format) -> Result<T, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+    let parsed = match format {
+        Format::Json => serde_json::from_str(&text).map_err(|err| err.to_string()),
+        // optional fields can be written without `Some(..)`
+        Format::Ron => ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_str(&text)
+            .map_err(|err| err.to_string()),
+        Format::Toml => toml::from_str(&text).map_err(|err| err.to_string()),
+    };
+    parsed.map_err(|err| format!("can't parse {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system's temporary one
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ttt-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn level() -> LevelData {
+        LevelData {
+            tower: vec![TowerData {
+                kind: "wood".to_string(),
+                x: 0.,
+                y: 0.,
+                h: 40.,
+                w: 20.,
+                rotation: 0.,
+            }],
+            targets: vec![TargetData {
+                x: 0.,
+                y: 60.,
+                path: None,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn loads_every_format() {
+        let dir = temp_dir("formats");
+        let json = dir.join("levels.json");
+        fs::write(&json, serde_json::to_string(&[level(), level()]).unwrap()).unwrap();
+        let ron = dir.join("levels.ron");
+        fs::write(
+            &ron,
+            r#"[
+                // implicit Some
+                (
+                    tower: [(kind: "wood", x: 0, y: 0, w: 20, h: 40)],
+                    targets: [(x: 0, y: 60)],
+                    bounds: (left: -500, right: 500, bottom: -100, top: 800),
+                ),
+            ]"#,
+        )
+        .unwrap();
+        let toml = dir.join("levels.toml");
+        fs::write(
+            &toml,
+            r#"
+                [[level]]
+                tower = [{ kind = "wood", x = 0.0, y = 0.0, w = 20.0, h = 40.0 }]
+                targets = [{ x = 0.0, y = 60.0 }]
+
+                [[level]]
+                tower = []
+                targets = [{ x = 10.0, y = 0.0 }]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(load_levels(&json).unwrap().len(), 2);
+        let ron = load_levels(&ron).unwrap();
+        assert_eq!(ron[0].bounds.as_ref().unwrap().right, 500.);
+        assert_eq!(load_levels(&toml).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn loads_a_directory_in_file_name_order() {
+        let dir = temp_dir("directory");
+        let mut second = level();
+        second.targets[0].x = 2.;
+        fs::write(dir.join("2.json"), serde_json::to_string(&second).unwrap()).unwrap();
+        fs::write(
+            dir.join("1.toml"),
+            "tower = []\n[[targets]]\nx = 1.0\ny = 0.0\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "not a level").unwrap();
+
+        let levels = load_levels(&dir).unwrap();
+        let xs: Vec<f32> = levels.iter().map(|level| level.targets[0].x).collect();
+        assert_eq!(xs, [1., 2.]);
+    }
+
+    #[test]
+    fn reports_files_that_cant_be_used() {
+        let dir = temp_dir("errors");
+        let broken = dir.join("broken.json");
+        fs::write(&broken, "[{").unwrap();
+        let empty = dir.join("empty.json");
+        fs::write(&empty, "[]").unwrap();
+
+        assert!(load_levels(&broken).unwrap_err().starts_with("can't parse"));
+        assert!(load_levels(&empty).unwrap_err().contains("has no levels"));
+        assert!(load_levels(&dir.join("missing.json"))
+            .unwrap_err()
+            .starts_with("can't read"));
+    }
+
+    #[test]
+    fn refuses_levels_that_cant_be_played() {
+        let refused = |change: fn(&mut LevelData)| {
+            let mut level = level();
+            change(&mut level);
+            validate_level(&level).unwrap_err()
+        };
+
+        assert!(validate_level(&level()).is_ok());
+        assert!(refused(|l| l.targets.clear()).contains("no targets"));
+        assert!(refused(|l| l.tower[0].w = 0.).contains("no size"));
+        assert!(refused(|l| l.tower[0].x = f32::NAN).contains("isn't finite"));
+        assert!(refused(|l| l.targets[0].y = f32::INFINITY).contains("isn't finite"));
+        assert!(refused(|l| {
+            l.terrain = vec![PointData { x: 10., y: 0. }, PointData { x: -10., y: 0. }]
+        })
+        .contains("terrain point 1"));
+        // a cliff
+        let mut cliff = level();
+        cliff.terrain = vec![PointData { x: 10., y: 0. }, PointData { x: 10., y: 40. }];
+        assert!(validate_level(&cliff).is_ok());
+        assert!(refused(|l| {
+            l.bounds = Some(BoundsData {
+                left: 100.,
+                right: -100.,
+                bottom: 0.,
+                top: 500.,
+            })
+        })
+        .contains("inside out"));
+        assert!(refused(|l| {
+            l.targets[0].path = Some(PathData {
+                points: vec![PointData { x: 50., y: 0. }],
+                speed: 0.,
+            })
+        })
+        .contains("speed"));
+        assert!(refused(|l| {
+            l.movers.push(MoverData {
+                x: 0.,
+                y: 100.,
+                w: 10.,
+                h: 10.,
+                path: None,
+                angular_speed: 0.,
+                pendulum: Some(PendulumData {
+                    length: 50.,
+                    amplitude: 30.,
+                    period: -1.,
+                }),
+            })
+        })
+        .contains("period"));
+        assert!(refused(|l| {
+            l.joints.push(JointData {
+                kind: "glue".to_string(),
+                a: BodyRef::Tower(3),
+                b: None,
+                x: 0.,
+                y: 0.,
+                strength: None,
+                limits: None,
+                length: None,
+            })
+        })
+        .contains("missing body"));
+        assert!(refused(|l| {
+            l.joints.push(JointData {
+                kind: "rope".to_string(),
+                a: BodyRef::Target(0),
+                b: None,
+                x: 0.,
+                y: 100.,
+                strength: None,
+                limits: None,
+                length: Some(0.),
+            })
+        })
+        .contains("length"));
+    }
+}
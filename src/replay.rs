@@ -0,0 +1,268 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude as lyon;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::actions::{Action, Actions};
+use crate::constants::REPLAY_SECONDS;
+use crate::game::{Game, LevelStarted, LevelState, Paused, ShotFired};
+use crate::leaderboard::NameEntry;
+use crate::settings::Settings;
+use crate::terrain::Ground;
+
+/// What a recorded body looks like, to draw it again after it is gone
+struct Look {
+    /// Radius of a ball, or zero for a box of `size`
+    radius: f32,
+    size: Vec2,
+    draw_mode: lyon::DrawMode,
+}
+
+/// Every moving body over the last seconds of the shot
+#[derive(Default)]
+pub struct Recording {
+    looks: HashMap<Entity, Look>,
+    /// Real time since the shot was fired, and where everything was
+    frames: VecDeque<(f32, Vec<(Entity, Transform)>)>,
+    time: f32,
+}
+
+/// Present while the instant replay plays. The game stays paused under it
+pub struct Replaying {
+    time: f32,
+    ghosts: HashMap<Entity, Entity>,
+}
+
+/// Stand-in drawn for a recorded body during the replay
+#[derive(Component)]
+struct Ghost;
+
+#[derive(Component)]
+struct ReplayText;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Recording>()
+            .add_exit_system(AppState::InGame, end_replay)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::InGame)
+                    .run_unless_resource_exists::<Paused>()
+                    .with_system(record)
+                    .into(),
+            )
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::InGame)
+                    .run_unless_resource_exists::<NameEntry>()
+                    .with_system(toggle_replay)
+                    .with_system(play_replay.run_if_resource_exists::<Replaying>())
+                    .into(),
+            );
+    }
+}
+
+fn record(
+    time: Res<Time>,
+    game: Res<Game>,
+    mut recording: ResMut<Recording>,
+    mut fired_events: EventReader<ShotFired>,
+    mut started_events: EventReader<LevelStarted>,
+    bodies: Query<
+        (Entity, &Transform, &Collider, &lyon::DrawMode),
+        (With<RigidBody>, Without<Ground>),
+    >,
+) {
+    if fired_events.iter().count() > 0 || started_events.iter().count() > 0 {
+        *recording = Recording::default();
+    }
+
+    if game.state != LevelState::Launched {
+        return;
+    }
+
+    recording.time += time.delta_seconds();
+    let now = recording.time;
+    let mut frame = Vec::new();
+    for (ent, trans, collider, draw_mode) in bodies.iter() {
+        frame.push((ent, *trans));
+        recording.looks.entry(ent).or_insert_with(|| Look {
+            radius: collider.as_ball().map_or(0., |ball| ball.radius()),
+            size: collider
+                .as_cuboid()
+                .map_or(Vec2::ZERO, |c| c.half_extents() * 2.),
+            draw_mode: *draw_mode,
+        });
+    }
+    recording.frames.push_back((now, frame));
+
+    while let Some((t, _)) = recording.frames.front() {
+        if *t >= now - REPLAY_SECONDS {
+            break;
+        }
+        recording.frames.pop_front();
+    }
+}
+
+/// Starts the replay once a shot is over, or cuts it short
+fn toggle_replay(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    game: Res<Game>,
+    settings: Res<Settings>,
+    asset_server: Res<AssetServer>,
+    recording: Res<Recording>,
+    replaying: Option<Res<Replaying>>,
+    paused: Option<Res<Paused>>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut bodies: Query<&mut Visibility, (With<RigidBody>, Without<Ground>)>,
+    ghosts: Query<Entity, Or<(With<Ghost>, With<ReplayText>)>>,
+) {
+    if !actions.just_pressed(Action::Replay) {
+        return;
+    }
+
+    if replaying.is_some() {
+        stop_replay(&mut commands, &mut rapier_config, &mut bodies, &ghosts);
+        return;
+    }
+
+    if paused.is_some() || game.state == LevelState::Launched || recording.frames.is_empty() {
+        return;
+    }
+
+    commands.insert_resource(Paused);
+    rapier_config.physics_pipeline_active = false;
+    for mut visibility in bodies.iter_mut() {
+        visibility.is_visible = false;
+    }
+
+    let mut ghosts = HashMap::new();
+    for (ent, look) in recording.looks.iter() {
+        let ghost = if look.radius > 0. {
+            lyon::GeometryBuilder::build_as(
+                &lyon::shapes::Circle {
+                    radius: look.radius,
+                    center: Vec2::ZERO,
+                },
+                look.draw_mode,
+                Transform::default(),
+            )
+        } else {
+            lyon::GeometryBuilder::build_as(
+                &lyon::shapes::Rectangle {
+                    extents: look.size,
+                    origin: lyon::shapes::RectangleOrigin::Center,
+                },
+                look.draw_mode,
+                Transform::default(),
+            )
+        };
+        ghosts.insert(*ent, commands.spawn_bundle(ghost).insert(Ghost).id());
+    }
+
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(250.),
+                    left: Val::Px(300.),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::from_section(
+                format!("Replay (\"{}\" to skip)", settings.binding(Action::Replay)),
+                TextStyle {
+                    font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                    font_size: 30.0,
+                    color: Color::BLACK,
+                },
+            ),
+            ..default()
+        })
+        .insert(ReplayText);
+
+    commands.insert_resource(Replaying { time: 0., ghosts });
+}
+
+/// Moves the ghosts to where the bodies were, at the speed they moved
+fn play_replay(
+    mut commands: Commands,
+    time: Res<Time>,
+    recording: Res<Recording>,
+    mut replaying: ResMut<Replaying>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut bodies: Query<&mut Visibility, (With<RigidBody>, Without<Ground>)>,
+    mut ghost_transforms: Query<
+        (&mut Transform, &mut Visibility),
+        (With<Ghost>, Without<RigidBody>),
+    >,
+    ghosts: Query<Entity, Or<(With<Ghost>, With<ReplayText>)>>,
+) {
+    let start = match recording.frames.front() {
+        Some((t, _)) => *t,
+        None => 0.,
+    };
+    replaying.time += time.delta_seconds();
+
+    let frame = recording
+        .frames
+        .iter()
+        .find(|(t, _)| *t >= start + replaying.time);
+    let frame = match frame {
+        Some((_, frame)) => frame,
+        None => {
+            stop_replay(&mut commands, &mut rapier_config, &mut bodies, &ghosts);
+            return;
+        }
+    };
+
+    for (_, mut visibility) in ghost_transforms.iter_mut() {
+        visibility.is_visible = false;
+    }
+    for (ent, trans) in frame.iter() {
+        let ghost = replaying.ghosts.get(ent).copied();
+        if let Some(Ok((mut ghost_trans, mut visibility))) =
+            ghost.map(|ghost| ghost_transforms.get_mut(ghost))
+        {
+            *ghost_trans = *trans;
+            visibility.is_visible = true;
+        }
+    }
+}
+
+fn stop_replay(
+    commands: &mut Commands,
+    rapier_config: &mut RapierConfiguration,
+    bodies: &mut Query<&mut Visibility, (With<RigidBody>, Without<Ground>)>,
+    ghosts: &Query<Entity, Or<(With<Ghost>, With<ReplayText>)>>,
+) {
+    for ent in ghosts.iter() {
+        commands.entity(ent).despawn();
+    }
+    for mut visibility in bodies.iter_mut() {
+        visibility.is_visible = true;
+    }
+    rapier_config.physics_pipeline_active = true;
+    commands.remove_resource::<Replaying>();
+    commands.remove_resource::<Paused>();
+}
+
+fn end_replay(
+    mut commands: Commands,
+    mut recording: ResMut<Recording>,
+    ghosts: Query<Entity, Or<(With<Ghost>, With<ReplayText>)>>,
+) {
+    for ent in ghosts.iter() {
+        commands.entity(ent).despawn();
+    }
+    commands.remove_resource::<Replaying>();
+    *recording = Recording::default();
+}
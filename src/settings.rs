@@ -0,0 +1,535 @@
+use std::fs;
+
+use bevy::prelude::*;
+use bevy::window::{PresentMode, WindowMode, WindowResizeConstraints};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::AppState;
+use crate::actions::{Action, Binding, Bindings, GamepadBindings};
+use crate::cli::Options;
+use crate::constants::{HEIGHT, NORMAL_BUTTON, SETTINGS_PATH, SETTINGS_VERSION, UI_SCALES, WIDTH};
+use crate::main_menu::button_system;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum AimMode {
+    /// Launch towards the cursor
+    Cursor,
+    /// Pull back from the ball like a slingshot, launch away from the cursor
+    Slingshot,
+    /// Set the angle and power in small steps with the arrow keys
+    Keyboard,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Layout of the file, for telling an older one apart if it ever changes
+    pub version: u32,
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    pub aim_mode: AimMode,
+    pub trajectory_preview: bool,
+    pub particles: bool,
+    /// Fire starts the charge and a second press launches, instead of holding
+    pub lock_charge: bool,
+    pub ui_scale: f32,
+    pub bindings: Bindings,
+    pub gamepad_bindings: GamepadBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: SETTINGS_VERSION,
+            master_volume: 0.8,
+            music_volume: 0.6,
+            sfx_volume: 1.0,
+            fullscreen: false,
+            vsync: true,
+            aim_mode: AimMode::Cursor,
+            trajectory_preview: false,
+            particles: true,
+            lock_charge: false,
+            ui_scale: 1.0,
+            bindings: Bindings::default(),
+            gamepad_bindings: GamepadBindings::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the config file, falling back to the defaults when it is
+    /// missing or can't be parsed
+    pub fn load() -> Self {
+        let json = match fs::read_to_string(SETTINGS_PATH) {
+            Ok(json) => json,
+            Err(_) => return Settings::default(),
+        };
+
+        Settings::parse(&json).unwrap_or_else(|err| {
+            warn!("ignoring invalid settings in {}: {}", SETTINGS_PATH, err);
+            Settings::default()
+        })
+    }
+
+    fn parse(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// The binding of an action, its default depending on the aim mode
+    pub fn binding(&self, action: Action) -> Binding {
+        self.bindings.get(action, self.aim_mode)
+    }
+
+    pub fn save(&self) {
+        let json = serde_json::to_string_pretty(self).unwrap();
+        if let Err(err) = fs::write(SETTINGS_PATH, json) {
+            warn!("could not save settings to {}: {}", SETTINGS_PATH, err);
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Debug)]
+enum SettingBtn {
+    MasterVolume,
+    MusicVolume,
+    SfxVolume,
+    Fullscreen,
+    VSync,
+    AimMode,
+    Trajectory,
+    Particles,
+    LockCharge,
+    UiScale,
+    Bind(Action),
+    Back,
+}
+
+/// Waiting for the key or mouse button to bind to an action
+struct Rebinding(Action);
+
+/// Unscaled font size of a piece of UI text, captured the first time it is seen
+#[derive(Component)]
+struct BaseFontSize(f32);
+
+#[derive(Component)]
+struct SettingsNode;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Settings::load())
+            .add_enter_system(AppState::Settings, setup_settings_menu)
+            .add_exit_system(AppState::Settings, remove_settings_menu)
+            .add_system(apply_settings)
+            .add_system(scale_ui_text)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::Settings)
+                    .with_system(settings_buttons)
+                    .with_system(rebind_key.run_if_resource_exists::<Rebinding>())
+                    .with_system(update_labels)
+                    .with_system(button_system)
+                    .into(),
+            );
+    }
+}
+
+fn label(btn: SettingBtn, settings: &Settings, rebinding: Option<&Rebinding>) -> String {
+    let on_off = |b: bool| if b { "On" } else { "Off" };
+
+    match btn {
+        SettingBtn::MasterVolume => format!("Volume: {:.0}%", settings.master_volume * 100.),
+        SettingBtn::MusicVolume => format!("Music: {:.0}%", settings.music_volume * 100.),
+        SettingBtn::SfxVolume => format!("Effects: {:.0}%", settings.sfx_volume * 100.),
+        SettingBtn::Fullscreen => format!("Fullscreen: {}", on_off(settings.fullscreen)),
+        SettingBtn::VSync => format!("VSync: {}", on_off(settings.vsync)),
+        SettingBtn::AimMode => format!("Aim: {:?}", settings.aim_mode),
+        SettingBtn::Trajectory => format!("Trajectory: {}", on_off(settings.trajectory_preview)),
+        SettingBtn::Particles => format!("Particles: {}", on_off(settings.particles)),
+        SettingBtn::LockCharge => format!(
+            "Charge: {}",
+            if settings.lock_charge {
+                "Toggle"
+            } else {
+                "Hold"
+            }
+        ),
+        SettingBtn::UiScale => format!("UI scale: {:.0}%", settings.ui_scale * 100.),
+        SettingBtn::Bind(action) => match rebinding {
+            Some(Rebinding(a)) if *a == action => format!("{}: press...", action.label()),
+            _ => format!("{}: {}", action.label(), settings.binding(action)),
+        },
+        SettingBtn::Back => "Back".to_string(),
+    }
+}
+
+fn setting_button(
+    parent: &mut ChildBuilder,
+    asset_server: &AssetServer,
+    settings: &Settings,
+    btn: SettingBtn,
+) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(330.0), Val::Px(30.0)),
+                margin: UiRect::all(Val::Px(3.)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: NORMAL_BUTTON.into(),
+            ..default()
+        })
+        .insert(btn)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::from_section(
+                    label(btn, settings, None),
+                    TextStyle {
+                        font: asset_server.load("fonts/JetBrainsMono-Regular.ttf"),
+                        font_size: 20.0,
+                        color: Color::rgb(0.9, 0.9, 0.9),
+                    },
+                ),
+                ..default()
+            });
+        });
+}
+
+fn setup_settings_menu(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    asset_server: Res<AssetServer>,
+) {
+    let options = [
+        SettingBtn::MasterVolume,
+        SettingBtn::MusicVolume,
+        SettingBtn::SfxVolume,
+        SettingBtn::Fullscreen,
+        SettingBtn::VSync,
+        SettingBtn::AimMode,
+        SettingBtn::Trajectory,
+        SettingBtn::Particles,
+        SettingBtn::LockCharge,
+        SettingBtn::UiScale,
+    ];
+
+    let column = NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::ColumnReverse,
+            margin: UiRect::all(Val::Px(10.)),
+            ..default()
+        },
+        color: Color::NONE.into(),
+        ..default()
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(SettingsNode)
+        .with_children(|container| {
+            container.spawn_bundle(TextBundle {
+                text: Text::from_section(
+                    "Settings",
+                    TextStyle {
+                        font: asset_server.load("fonts/JetBrainsMono-Bold.ttf"),
+                        font_size: 50.0,
+                        color: Color::BLACK,
+                    },
+                ),
+                ..default()
+            });
+
+            // general options on the left, controls on the right
+            container
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        align_items: AlignItems::FlexStart,
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|columns| {
+                    columns
+                        .spawn_bundle(column.clone())
+                        .with_children(|parent| {
+                            for btn in options {
+                                setting_button(parent, &asset_server, &settings, btn);
+                            }
+                        });
+                    columns.spawn_bundle(column).with_children(|parent| {
+                        for action in Action::ALL {
+                            let btn = SettingBtn::Bind(action);
+                            setting_button(parent, &asset_server, &settings, btn);
+                        }
+                    });
+                });
+
+            setting_button(container, &asset_server, &settings, SettingBtn::Back);
+        });
+}
+
+fn remove_settings_menu(mut commands: Commands, query: Query<Entity, With<SettingsNode>>) {
+    for ent in query.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+    commands.remove_resource::<Rebinding>();
+}
+
+/// Steps a volume up by 10%, wrapping from 100% back to silent
+fn next_volume(volume: f32) -> f32 {
+    ((volume * 10.).round() + 1.) % 11. / 10.
+}
+
+fn settings_buttons(
+    mut commands: Commands,
+    mut settings: ResMut<Settings>,
+    rebinding: Option<Res<Rebinding>>,
+    query: Query<(&Interaction, &SettingBtn), Changed<Interaction>>,
+) {
+    // a click while rebinding is the new binding, not a button press
+    if rebinding.is_some() {
+        return;
+    }
+
+    for (interaction, btn) in query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match btn {
+            SettingBtn::MasterVolume => {
+                settings.master_volume = next_volume(settings.master_volume)
+            }
+            SettingBtn::MusicVolume => settings.music_volume = next_volume(settings.music_volume),
+            SettingBtn::SfxVolume => settings.sfx_volume = next_volume(settings.sfx_volume),
+            SettingBtn::Fullscreen => settings.fullscreen = !settings.fullscreen,
+            SettingBtn::VSync => settings.vsync = !settings.vsync,
+            SettingBtn::AimMode => {
+                settings.aim_mode = match settings.aim_mode {
+                    AimMode::Cursor => AimMode::Slingshot,
+                    AimMode::Slingshot => AimMode::Keyboard,
+                    AimMode::Keyboard => AimMode::Cursor,
+                };
+            }
+            SettingBtn::Trajectory => {
+                settings.trajectory_preview = !settings.trajectory_preview;
+            }
+            SettingBtn::Particles => settings.particles = !settings.particles,
+            SettingBtn::LockCharge => settings.lock_charge = !settings.lock_charge,
+            SettingBtn::UiScale => {
+                let next = UI_SCALES
+                    .iter()
+                    .position(|s| (*s - settings.ui_scale).abs() < 0.01)
+                    .map_or(0, |i| (i + 1) % UI_SCALES.len());
+                settings.ui_scale = UI_SCALES[next];
+            }
+            SettingBtn::Bind(action) => commands.insert_resource(Rebinding(*action)),
+            SettingBtn::Back => commands.insert_resource(NextState(AppState::MainMenu)),
+        }
+    }
+}
+
+fn rebind_key(
+    mut commands: Commands,
+    mut settings: ResMut<Settings>,
+    rebinding: Res<Rebinding>,
+    keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    pad_buttons: Res<Input<GamepadButton>>,
+) {
+    let binding = match (
+        keyboard.get_just_pressed().next(),
+        mouse.get_just_pressed().next(),
+    ) {
+        // escape keeps the old binding, as does a gamepad button since those
+        // are bound in the config file
+        (Some(KeyCode::Escape), _) => None,
+        _ if pad_buttons.get_just_pressed().next().is_some() => None,
+        (Some(key), _) => Some(Binding::Key(*key)),
+        (None, Some(button)) => Some(Binding::Mouse(*button)),
+        (None, None) => return,
+    };
+
+    if let Some(binding) = binding {
+        settings.bindings.set(rebinding.0, binding);
+    }
+    commands.remove_resource::<Rebinding>();
+}
+
+fn update_labels(
+    settings: Res<Settings>,
+    rebinding: Option<Res<Rebinding>>,
+    buttons: Query<(&SettingBtn, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    // the resource can't be checked for removal, so refresh every frame
+    for (btn, children) in buttons.iter() {
+        let value = label(*btn, &settings, rebinding.as_deref());
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                if text.sections[0].value != value {
+                    text.sections[0].value = value.clone();
+                }
+            }
+        }
+    }
+}
+
+/// Applies and saves the settings whenever they change, including the
+/// first frame so the saved window options take effect at startup. A window
+/// keeps the `--size` it was given
+fn apply_settings(
+    settings: Res<Settings>,
+    options: Option<Res<Options>>,
+    mut windows: ResMut<Windows>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    if !settings.is_added() {
+        settings.save();
+    }
+
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+
+    if settings.fullscreen {
+        window.set_resize_constraints(WindowResizeConstraints::default());
+        window.set_mode(WindowMode::BorderlessFullscreen);
+    } else {
+        window.set_mode(WindowMode::Windowed);
+        let (width, height) =
+            options.map_or((WIDTH, HEIGHT), |options| (options.width, options.height));
+        window.set_resolution(width, height);
+        window.set_resize_constraints(WindowResizeConstraints {
+            min_width: width,
+            max_width: width,
+            min_height: height,
+            max_height: height,
+        });
+    }
+
+    window.set_present_mode(if settings.vsync {
+        PresentMode::AutoVsync
+    } else {
+        PresentMode::AutoNoVsync
+    });
+}
+
+fn scale_ui_text(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut new_texts: Query<(Entity, &mut Text), (With<Node>, Without<BaseFontSize>)>,
+    mut texts: Query<(&mut Text, &BaseFontSize)>,
+) {
+    for (ent, mut text) in new_texts.iter_mut() {
+        let base = text.sections[0].style.font_size;
+        commands.entity(ent).insert(BaseFontSize(base));
+        for section in text.sections.iter_mut() {
+            section.style.font_size = base * settings.ui_scale;
+        }
+    }
+
+    if settings.is_changed() {
+        for (mut text, base) in texts.iter_mut() {
+            for section in text.sections.iter_mut() {
+                section.style.font_size = base.0 * settings.ui_scale;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::window::WindowId;
+    use raw_window_handle::{RawWindowHandle, WebHandle};
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        let settings = Settings::parse(r#"{ "fullscreen": true }"#).unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                fullscreen: true,
+                ..default()
+            }
+        );
+        // `load` falls back to the defaults for these
+        assert!(Settings::parse(r#"{ "master_volume": "loud" }"#).is_err());
+    }
+
+    #[test]
+    fn saved_settings_read_back() {
+        let mut settings = Settings {
+            sfx_volume: 0.3,
+            ..default()
+        };
+        settings
+            .bindings
+            .set(Action::Undo, Binding::Key(KeyCode::Z));
+
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(Settings::parse(&json).unwrap(), settings);
+    }
+
+    #[test]
+    fn the_window_keeps_the_size_asked_for() {
+        let options = Options {
+            width: 1024.,
+            height: 768.,
+            ..default()
+        };
+        let descriptor = WindowDescriptor {
+            width: options.width,
+            height: options.height,
+            ..default()
+        };
+        let mut windows = Windows::default();
+        windows.add(Window::new(
+            WindowId::primary(),
+            &descriptor,
+            1024,
+            768,
+            1.,
+            None,
+            RawWindowHandle::Web(WebHandle::empty()),
+        ));
+
+        let mut app = App::new();
+        app.insert_resource(windows)
+            .insert_resource(options)
+            .insert_resource(Settings::default())
+            .add_system(apply_settings);
+        app.update();
+
+        let window = app.world.resource::<Windows>().primary();
+        assert_eq!(
+            (window.requested_width(), window.requested_height()),
+            (1024., 768.)
+        );
+    }
+}
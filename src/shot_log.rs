@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::AppState;
+use crate::constants::{LAUNCH_FACTOR, SHOT_LOG_DELAY};
+use crate::game::{
+    Ball, CurrentLevel, Game, LaunchEvent, LevelStarted, LevelState, Paused, ShotFired,
+};
+use crate::headless::Shot;
+use crate::read_levels::{validate_level, LevelData};
+use crate::undo::ShotUndone;
+
+/// A level and the shots taken at it, as written by `--shots` and played
+/// back by `--mode replay`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShotLog {
+    pub level: LevelData,
+    pub shots: Vec<Shot>,
+}
+
+impl ShotLog {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+        let log: ShotLog = serde_json::from_str(&json)
+            .map_err(|err| format!("{} isn't a shot file: {}", path.display(), err))?;
+        validate_level(&log.level)
+            .map_err(|err| format!("{}: the level can't be played: {}", path.display(), err))?;
+        Ok(log)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).unwrap();
+        fs::write(path, json).map_err(|err| format!("can't write {}: {}", path.display(), err))
+    }
+}
+
+impl Shot {
+    /// The shot that gave the ball `impulse`, the inverse of `launch_impulse`
+    pub fn from_impulse(impulse: Vec2) -> Self {
+        Shot {
+            angle: impulse.y.atan2(impulse.x).to_degrees(),
+            power: impulse.length() / LAUNCH_FACTOR,
+        }
+    }
+}
+
+/// Writes the shots taken at the level being played to `path` as they are
+/// fired, starting over with each level
+pub struct ShotRecorder {
+    pub path: PathBuf,
+    log: ShotLog,
+}
+
+impl ShotRecorder {
+    pub fn new(path: PathBuf) -> Self {
+        ShotRecorder {
+            path,
+            log: ShotLog::default(),
+        }
+    }
+}
+
+/// Shots still to be fired, one each time the ball is ready again
+pub struct Playback {
+    shots: VecDeque<Shot>,
+    wait: Timer,
+}
+
+impl Playback {
+    pub fn new(shots: Vec<Shot>) -> Self {
+        Playback {
+            shots: shots.into(),
+            wait: Timer::from_seconds(SHOT_LOG_DELAY, false),
+        }
+    }
+}
+
+pub struct ShotLogPlugin;
+
+impl Plugin for ShotLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(AppState::InGame)
+                .run_unless_resource_exists::<Paused>()
+                .with_system(record_shots.run_if_resource_exists::<ShotRecorder>())
+                .with_system(play_back_shots.run_if_resource_exists::<Playback>())
+                .into(),
+        );
+    }
+}
+
+fn record_shots(
+    mut recorder: ResMut<ShotRecorder>,
+    mut started: EventReader<LevelStarted>,
+    mut fired: EventReader<ShotFired>,
+    mut undone: EventReader<ShotUndone>,
+    level: Option<Res<CurrentLevel>>,
+) {
+    let recorder = &mut *recorder;
+    let mut changed = false;
+    if started.iter().count() > 0 {
+        recorder.log.shots.clear();
+    }
+    for ev in fired.iter() {
+        recorder.log.shots.push(Shot::from_impulse(ev.impulse));
+        changed = true;
+    }
+    for _ in undone.iter() {
+        recorder.log.shots.pop();
+        changed = true;
+    }
+    if !changed {
+        return;
+    }
+
+    if let Some(level) = level {
+        recorder.log.level = level.0.clone();
+    }
+    if let Err(err) = recorder.log.save(&recorder.path) {
+        warn!("{}", err);
+    }
+}
+
+/// Fires the next shot once the ball has been ready for a moment, so each
+/// can be watched landing
+fn play_back_shots(
+    mut playback: ResMut<Playback>,
+    time: Res<Time>,
+    game: Res<Game>,
+    ball: Query<&Transform, With<Ball>>,
+    mut launches: EventWriter<LaunchEvent>,
+) {
+    if game.state != LevelState::Prelaunch {
+        playback.wait.reset();
+        return;
+    }
+    if !playback.wait.tick(time.delta()).finished() {
+        return;
+    }
+    let (ball, shot) = match (ball.get_single(), playback.shots.front()) {
+        (Ok(ball), Some(shot)) => (ball.translation.truncate(), *shot),
+        _ => return,
+    };
+
+    launches.send(LaunchEvent {
+        target: ball + shot.direction(),
+        power: shot.power,
+    });
+    playback.shots.pop_front();
+    playback.wait.reset();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::launch_impulse;
+
+    #[test]
+    fn shots_are_read_back_from_the_impulse() {
+        let shot = Shot {
+            angle: 37.5,
+            power: 420.,
+        };
+        let from = Vec2::new(-450., 20.);
+        let impulse = launch_impulse(from, from + shot.direction(), shot.power);
+        let read = Shot::from_impulse(impulse);
+        assert!((read.angle - shot.angle).abs() < 1e-3);
+        assert!((read.power - shot.power).abs() < 1e-2);
+    }
+
+    #[test]
+    fn shot_files_read_back() {
+        let log = ShotLog {
+            level: LevelData {
+                targets: vec![crate::read_levels::TargetData {
+                    x: 100.,
+                    y: 0.,
+                    path: None,
+                }],
+                ..Default::default()
+            },
+            shots: vec![Shot {
+                angle: 45.,
+                power: 300.,
+            }],
+        };
+        let path = std::env::temp_dir().join("trash_the_tower_shots_test.json");
+        log.save(&path).unwrap();
+        let read = ShotLog::load(&path);
+        fs::remove_file(&path).unwrap();
+        let read = read.unwrap();
+        assert_eq!(read.shots, log.shots);
+        assert_eq!(read.level.targets.len(), 1);
+    }
+}
@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::constants::{SLOWMO_DURATION, SLOWMO_IMPACT_FORCE, SLOWMO_SCALE};
+use crate::game::{Ball, Paused, ShotFired, TargetDestroyed};
+
+/// Speed of the simulation. `base` is the normal speed, changed from the
+/// console, and the slow motion eases back to it
+pub struct TimeScale {
+    pub base: f32,
+    /// Real seconds of slow motion left
+    slow: f32,
+    /// Whether the ball has had its heavy hit this shot
+    hit: bool,
+}
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        TimeScale {
+            base: 1.,
+            slow: 0.,
+            hit: false,
+        }
+    }
+}
+
+impl TimeScale {
+    /// Slows the simulation down from now
+    pub fn slow_down(&mut self) {
+        self.slow = SLOWMO_DURATION;
+    }
+
+    pub fn current(&self) -> f32 {
+        // full slow motion for the first half, then easing back
+        let t = (self.slow / (SLOWMO_DURATION / 2.)).min(1.);
+        self.base * (1. + (SLOWMO_SCALE - 1.) * t)
+    }
+}
+
+pub struct SlowMoPlugin;
+
+impl Plugin for SlowMoPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeScale>()
+            .add_exit_system(AppState::InGame, reset_time_scale)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::InGame)
+                    .run_unless_resource_exists::<Paused>()
+                    .with_system(trigger_slowmo)
+                    .with_system(apply_time_scale)
+                    .into(),
+            );
+    }
+}
+
+fn trigger_slowmo(
+    mut time_scale: ResMut<TimeScale>,
+    mut fired_events: EventReader<ShotFired>,
+    mut destroyed_events: EventReader<TargetDestroyed>,
+    mut contact_force_events: EventReader<ContactForceEvent>,
+    balls: Query<&Ball>,
+) {
+    if fired_events.iter().count() > 0 {
+        time_scale.hit = false;
+    }
+
+    if destroyed_events.iter().count() > 0 {
+        time_scale.slow_down();
+    }
+
+    let heavy_hit = contact_force_events.iter().any(|ev| {
+        ev.total_force_magnitude > SLOWMO_IMPACT_FORCE
+            && (balls.contains(ev.collider1) || balls.contains(ev.collider2))
+    });
+    if heavy_hit && !time_scale.hit {
+        time_scale.hit = true;
+        time_scale.slow_down();
+    }
+}
+
+fn apply_time_scale(
+    time: Res<Time>,
+    mut time_scale: ResMut<TimeScale>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    time_scale.slow = (time_scale.slow - time.delta_seconds()).max(0.);
+
+    let current = time_scale.current();
+    if let TimestepMode::Variable { time_scale, .. } = &mut rapier_config.timestep_mode {
+        if *time_scale != current {
+            *time_scale = current;
+        }
+    }
+}
+
+fn reset_time_scale(
+    mut time_scale: ResMut<TimeScale>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    time_scale.slow = 0.;
+    if let TimestepMode::Variable {
+        time_scale: scale, ..
+    } = &mut rapier_config.timestep_mode
+    {
+        *scale = time_scale.base;
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+
+use crate::constants::{
+    MAX_POWER, SOLVER_ANGLE_STEP, SOLVER_MAX_ANGLE, SOLVER_MAX_SHOTS, SOLVER_MIN_ANGLE,
+    SOLVER_POWER_STEP,
+};
+use crate::headless::{is_stable, settle_level, HeadlessLevel, Shot};
+use crate::read_levels::LevelData;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    VeryHard,
+    Unsolved,
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+            Difficulty::VeryHard => "very hard",
+            Difficulty::Unsolved => "unsolved",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+#[derive(Debug)]
+pub struct LevelReport {
+    pub level: usize,
+    pub samples: usize,
+    /// Fraction of single shots in the sweep that clear the level
+    pub win_rate: f32,
+    /// Every winning sequence found, all `greedy_shots` long
+    pub winning_shots: Vec<Vec<Shot>>,
+    /// Shots the greedy search needed. An upper bound on the fewest that
+    /// can win, as it only follows up on the best shot of each turn
+    pub greedy_shots: Option<usize>,
+    pub difficulty: Difficulty,
+}
+
+impl fmt::Display for LevelReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.greedy_shots {
+            Some(n) => writeln!(
+                f,
+                "Level {}: {} (greedy shots: {}, {:.1}% of {} single shots win)",
+                self.level,
+                self.difficulty,
+                n,
+                self.win_rate * 100.,
+                self.samples
+            )?,
+            None => writeln!(
+                f,
+                "Level {}: {} (no win within {} shots, {} shots tried per turn)",
+                self.level, self.difficulty, SOLVER_MAX_SHOTS, self.samples
+            )?,
+        }
+
+        for seq in self.winning_shots.iter() {
+            let shots: Vec<String> = seq
+                .iter()
+                .map(|s| format!("{:.0}° @ {:.0}", s.angle, s.power))
+                .collect();
+            writeln!(f, "    {}", shots.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Every shot the solver tries on each turn
+pub fn sweep() -> Vec<Shot> {
+    let mut shots = Vec::new();
+    let mut angle = SOLVER_MIN_ANGLE;
+    while angle <= SOLVER_MAX_ANGLE {
+        let mut power = SOLVER_POWER_STEP;
+        while power <= MAX_POWER {
+            shots.push(Shot { angle, power });
+            power += SOLVER_POWER_STEP;
+        }
+        angle += SOLVER_ANGLE_STEP;
+    }
+    shots
+}
+
+/// Plays a sequence of shots on a fresh copy of the level and returns the
+/// number of targets left
+pub fn play(level: &LevelData, shots: &[Shot]) -> usize {
+    let mut sim = HeadlessLevel::new(level);
+    let mut left = sim.targets_left();
+    for shot in shots {
+        left = sim.fire(shot);
+        if left == 0 {
+            break;
+        }
+    }
+    left
+}
+
+/// Searches for winning shots. When no single shot wins, the shot that
+/// destroys the most targets is kept and the sweep is repeated after it,
+/// up to `SOLVER_MAX_SHOTS` turns. `level` is played as given, so it should
+/// already be settled as the game settles it
+pub fn solve_level(index: usize, level: &LevelData) -> LevelReport {
+    let candidates = sweep();
+    let mut prefix: Vec<Shot> = Vec::new();
+    let mut remaining = level.targets.len();
+    let mut win_rate = 0.;
+
+    for turn in 1..=SOLVER_MAX_SHOTS {
+        let results: Vec<(Shot, usize)> = candidates
+            .iter()
+            .map(|shot| {
+                let mut seq = prefix.clone();
+                seq.push(*shot);
+                (*shot, play(level, &seq))
+            })
+            .collect();
+
+        let wins: Vec<Vec<Shot>> = results
+            .iter()
+            .filter(|(_, left)| *left == 0)
+            .map(|(shot, _)| {
+                let mut seq = prefix.clone();
+                seq.push(*shot);
+                seq
+            })
+            .collect();
+
+        if turn == 1 {
+            win_rate = wins.len() as f32 / candidates.len() as f32;
+        }
+
+        if !wins.is_empty() {
+            let difficulty = match turn {
+                1 if win_rate >= 0.2 => Difficulty::Easy,
+                1 if win_rate >= 0.05 => Difficulty::Medium,
+                1 => Difficulty::Hard,
+                _ => Difficulty::VeryHard,
+            };
+            return LevelReport {
+                level: index,
+                samples: candidates.len(),
+                win_rate,
+                winning_shots: wins,
+                greedy_shots: Some(turn),
+                difficulty,
+            };
+        }
+
+        // keep the most destructive shot and look for a follow-up
+        let (best, left) = results.iter().min_by_key(|(_, left)| *left).unwrap();
+        if *left >= remaining {
+            break;
+        }
+        remaining = *left;
+        prefix.push(*best);
+    }
+
+    LevelReport {
+        level: index,
+        samples: candidates.len(),
+        win_rate,
+        winning_shots: Vec::new(),
+        greedy_shots: None,
+        difficulty: Difficulty::Unsolved,
+    }
+}
+
+/// Solves every level once settled, as it would be played
+pub fn solve_levels(levels: &[LevelData]) {
+    for (i, level) in levels.iter().enumerate() {
+        print!("{}", solve_level(i, &settle_level(level).level));
+    }
+}
+
+/// Checks every level settles without falling apart, then stands on its own
+/// and can be won as it would be played, printing a report for each. False
+/// if any fails
+pub fn verify_levels(levels: &[LevelData]) -> bool {
+    let mut ok = true;
+    for (i, level) in levels.iter().enumerate() {
+        let settled = settle_level(level);
+        let problem = if settled.collapsed {
+            Some("the tower collapses before it is hit")
+        } else if settled.targets_lost > 0 {
+            Some("targets are lost before the first shot")
+        } else if !settled.rested || !is_stable(&settled.level) {
+            Some("unstable, the tower moves before it is hit")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            println!("Level {}: {}", i, problem);
+            ok = false;
+            continue;
+        }
+
+        let report = solve_level(i, &settled.level);
+        print!("{}", report);
+        ok &= report.greedy_shots.is_some();
+    }
+    ok
+}
@@ -0,0 +1,343 @@
+use std::f32::consts::TAU;
+
+use bevy::audio::AudioSink;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::AppState;
+use crate::constants::{Impact, IMPACT_FULL_FORCE, IMPACT_SOUND_FORCE, MAX_IMPACT_SOUNDS};
+use crate::game::{BlockMaterial, TargetDestroyed};
+use crate::settings::Settings;
+
+const SAMPLE_RATE: u32 = 22050;
+
+/// One-off sounds other plugins ask for
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sound {
+    Launch,
+    LevelComplete,
+    ButtonHover,
+    ButtonClick,
+}
+
+pub struct PlaySound(pub Sound);
+
+/// Every sound in the game. There are no audio files, they are all
+/// synthesized at startup
+struct Sounds {
+    launch: Handle<AudioSource>,
+    wood_impact: Handle<AudioSource>,
+    stone_impact: Handle<AudioSource>,
+    target: Handle<AudioSource>,
+    level_complete: Handle<AudioSource>,
+    hover: Handle<AudioSource>,
+    click: Handle<AudioSource>,
+    menu_music: Handle<AudioSource>,
+    game_music: Handle<AudioSource>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Track {
+    Menu,
+    Game,
+}
+
+/// The music playing now. The sink never appears when there is no audio
+/// device, everything else works the same without one
+#[derive(Default)]
+struct Music {
+    track: Option<Track>,
+    sink: Handle<AudioSink>,
+}
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlaySound>()
+            .init_resource::<Music>()
+            .add_startup_system(synthesize_sounds)
+            .add_system(play_sounds)
+            .add_system(play_music)
+            .add_system(target_sounds.run_in_state(AppState::InGame))
+            .add_system(impact_sounds.run_in_state(AppState::InGame));
+    }
+}
+
+fn sfx_volume(settings: &Settings) -> f32 {
+    settings.master_volume * settings.sfx_volume
+}
+
+fn play_sounds(
+    audio: Res<Audio>,
+    sounds: Res<Sounds>,
+    settings: Res<Settings>,
+    mut events: EventReader<PlaySound>,
+) {
+    for PlaySound(sound) in events.iter() {
+        let (handle, volume) = match sound {
+            Sound::Launch => (&sounds.launch, 0.8),
+            Sound::LevelComplete => (&sounds.level_complete, 0.7),
+            Sound::ButtonHover => (&sounds.hover, 0.3),
+            Sound::ButtonClick => (&sounds.click, 0.5),
+        };
+        audio.play_with_settings(
+            handle.clone(),
+            PlaybackSettings::ONCE.with_volume(volume * sfx_volume(&settings)),
+        );
+    }
+}
+
+fn target_sounds(
+    audio: Res<Audio>,
+    sounds: Res<Sounds>,
+    settings: Res<Settings>,
+    mut events: EventReader<TargetDestroyed>,
+) {
+    for _ in events.iter() {
+        let speed = rand::thread_rng().gen_range(0.9..1.1);
+        audio.play_with_settings(
+            sounds.target.clone(),
+            PlaybackSettings::ONCE
+                .with_volume(0.8 * sfx_volume(&settings))
+                .with_speed(speed),
+        );
+    }
+}
+
+/// Plays the hardest few hits each frame, louder the harder the hit and
+/// pitched by the material of the block involved
+fn impact_sounds(
+    audio: Res<Audio>,
+    sounds: Res<Sounds>,
+    settings: Res<Settings>,
+    mut events: EventReader<ContactForceEvent>,
+    blocks: Query<&BlockMaterial>,
+) {
+    let mut hits: Vec<(f32, Impact, f32)> = events
+        .iter()
+        .filter(|ev| ev.total_force_magnitude > IMPACT_SOUND_FORCE)
+        .map(|ev| {
+            let material = blocks
+                .get(ev.collider1)
+                .or_else(|_| blocks.get(ev.collider2))
+                .ok();
+            let (impact, density) =
+                material.map_or((Impact::Stone, 1.0), |m| (m.0.impact, m.0.density));
+            (ev.total_force_magnitude, impact, density)
+        })
+        .collect();
+    hits.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut rng = rand::thread_rng();
+    for (force, impact, density) in hits.into_iter().take(MAX_IMPACT_SOUNDS) {
+        let handle = match impact {
+            Impact::Wood => &sounds.wood_impact,
+            Impact::Stone => &sounds.stone_impact,
+        };
+        // heavier blocks sound lower
+        let speed = rng.gen_range(0.9..1.1) / density.sqrt();
+        let volume = (force / IMPACT_FULL_FORCE).clamp(0.1, 1.0);
+        audio.play_with_settings(
+            handle.clone(),
+            PlaybackSettings::ONCE
+                .with_volume(volume * sfx_volume(&settings))
+                .with_speed(speed),
+        );
+    }
+}
+
+/// Switches the music with the screen and follows the volume settings
+fn play_music(
+    audio: Res<Audio>,
+    sounds: Res<Sounds>,
+    settings: Res<Settings>,
+    state: Res<CurrentState<AppState>>,
+    sinks: Res<Assets<AudioSink>>,
+    mut music: ResMut<Music>,
+) {
+    let volume = settings.master_volume * settings.music_volume;
+    let track = match state.0 {
+        AppState::InGame => Track::Game,
+        _ => Track::Menu,
+    };
+
+    if music.track != Some(track) {
+        if let Some(sink) = sinks.get(&music.sink) {
+            sink.stop();
+        }
+
+        let handle = match track {
+            Track::Menu => &sounds.menu_music,
+            Track::Game => &sounds.game_music,
+        };
+        let sink =
+            audio.play_with_settings(handle.clone(), PlaybackSettings::LOOP.with_volume(volume));
+        music.sink = sinks.get_handle(sink);
+        music.track = Some(track);
+    } else if settings.is_changed() {
+        if let Some(sink) = sinks.get(&music.sink) {
+            sink.set_volume(volume);
+        }
+    }
+}
+
+fn synthesize_sounds(mut commands: Commands, mut sources: ResMut<Assets<AudioSource>>) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut add = |samples: Vec<f32>| sources.add(wav(&samples));
+
+    let launch = mix(
+        tone(180., 520., 0.25, 8., sine),
+        scale(noise(&mut rng, 0.25, 10.), 0.3),
+    );
+    let wood_impact = mix(
+        tone(220., 160., 0.15, 30., triangle),
+        scale(noise(&mut rng, 0.08, 50.), 0.4),
+    );
+    let stone_impact = mix(
+        tone(110., 80., 0.2, 25., square),
+        scale(noise(&mut rng, 0.12, 35.), 0.6),
+    );
+    let target = mix(
+        tone(900., 250., 0.18, 15., sine),
+        scale(noise(&mut rng, 0.1, 30.), 0.3),
+    );
+    let level_complete = [72, 76, 79, 84]
+        .iter()
+        .flat_map(|note| tone(midi(*note), midi(*note), 0.14, 6., triangle))
+        .collect();
+
+    let sounds = Sounds {
+        launch: add(launch),
+        wood_impact: add(wood_impact),
+        stone_impact: add(stone_impact),
+        target: add(target),
+        level_complete: add(level_complete),
+        hover: add(tone(1200., 1200., 0.03, 60., sine)),
+        click: add(tone(800., 600., 0.06, 40., square)),
+        // roots A, F, C, G and E, C, G, D, arpeggiated in fifths and octaves
+        menu_music: add(music(&[57, 53, 48, 55], &[0, 7, 12, 19, 12, 7, 0, 7], 0.25)),
+        game_music: add(music(
+            &[52, 48, 55, 50],
+            &[0, 7, 12, 7, 19, 12, 7, 12],
+            0.16,
+        )),
+    };
+    commands.insert_resource(sounds);
+}
+
+fn sine(phase: f32) -> f32 {
+    (phase * TAU).sin()
+}
+
+fn triangle(phase: f32) -> f32 {
+    4. * (phase - (phase + 0.5).floor()).abs() - 1.
+}
+
+fn square(phase: f32) -> f32 {
+    if phase.fract() < 0.5 {
+        0.5
+    } else {
+        -0.5
+    }
+}
+
+fn midi(note: i32) -> f32 {
+    440. * 2f32.powf((note - 69) as f32 / 12.)
+}
+
+/// A tone gliding from `from` to `to` Hz, with a short attack and an
+/// exponential decay at `decay` per second
+fn tone(from: f32, to: f32, duration: f32, decay: f32, wave: fn(f32) -> f32) -> Vec<f32> {
+    let len = (duration * SAMPLE_RATE as f32) as usize;
+    let mut phase = 0.;
+    (0..len)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let freq = from + (to - from) * t / duration;
+            phase += freq / SAMPLE_RATE as f32;
+            let attack = (t / 0.005).min(1.);
+            wave(phase) * attack * (-decay * t).exp()
+        })
+        .collect()
+}
+
+fn noise(rng: &mut StdRng, duration: f32, decay: f32) -> Vec<f32> {
+    let len = (duration * SAMPLE_RATE as f32) as usize;
+    (0..len)
+        .map(|i| rng.gen_range(-1.0..1.0) * (-decay * i as f32 / SAMPLE_RATE as f32).exp())
+        .collect()
+}
+
+fn scale(mut samples: Vec<f32>, gain: f32) -> Vec<f32> {
+    samples.iter_mut().for_each(|s| *s *= gain);
+    samples
+}
+
+fn mix(mut a: Vec<f32>, b: Vec<f32>) -> Vec<f32> {
+    if a.len() < b.len() {
+        a.resize(b.len(), 0.);
+    }
+    for (i, s) in b.into_iter().enumerate() {
+        a[i] += s;
+    }
+    a
+}
+
+/// A loop of arpeggios, one bar per chord root, over a held bass note
+fn music(roots: &[i32], pattern: &[i32], note_len: f32) -> Vec<f32> {
+    let mut samples = Vec::new();
+    for root in roots {
+        let arpeggio: Vec<f32> = pattern
+            .iter()
+            .flat_map(|step| {
+                scale(
+                    tone(
+                        midi(root + 12 + step),
+                        midi(root + 12 + step),
+                        note_len,
+                        8.,
+                        triangle,
+                    ),
+                    0.25,
+                )
+            })
+            .collect();
+        let bar_len = arpeggio.len() as f32 / SAMPLE_RATE as f32;
+        let bass = scale(
+            tone(midi(root - 12), midi(root - 12), bar_len, 1.5, sine),
+            0.35,
+        );
+        samples.extend(mix(arpeggio, bass));
+    }
+    samples
+}
+
+/// Encodes samples as a mono 16 bit WAV file
+fn wav(samples: &[f32]) -> AudioSource {
+    let data_len = samples.len() as u32 * 2;
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // mono
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for s in samples {
+        let s = (s.clamp(-1., 1.) * i16::MAX as f32) as i16;
+        bytes.extend_from_slice(&s.to_le_bytes());
+    }
+
+    AudioSource {
+        bytes: bytes.into(),
+    }
+}
@@ -0,0 +1,216 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude as lyon;
+use bevy_rapier2d::prelude::*;
+
+use crate::constants::{GROUND_COLOR, GROUND_HEIGHT, HEIGHT, WIDTH, WORLD_CEILING, WORLD_MARGIN};
+use crate::read_levels::LevelData;
+
+#[derive(Component)]
+pub struct Ground;
+
+/// The surface of the ground in world coordinates, left to right. Flat
+/// unless the level gives its own
+pub struct Terrain {
+    pub points: Vec<Vec2>,
+    /// Whether the level left the ground flat
+    pub flat: bool,
+    win_w: f32,
+    win_h: f32,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Terrain::new(&LevelData::default(), WIDTH, HEIGHT)
+    }
+}
+
+impl Terrain {
+    /// Level terrain points are placed like the tower but measured up from
+    /// the flat ground, so `y: 0` is the usual ground level
+    pub fn new(level: &LevelData, win_w: f32, win_h: f32) -> Self {
+        let base = Vec2::new(win_w / 4., (-win_h / 2.) + GROUND_HEIGHT);
+        let mut points: Vec<Vec2> = level
+            .terrain
+            .iter()
+            .map(|p| base + Vec2::new(p.x, p.y))
+            .collect();
+
+        let flat = points.is_empty();
+        if flat {
+            points.push(Vec2::new(-win_w / 2., base.y));
+        }
+
+        // carry the ends on to the edges of the world
+        let first = points[0];
+        if first.x > -win_w / 2. {
+            points.insert(0, Vec2::new(-win_w / 2., first.y));
+        }
+        let last = points[points.len() - 1];
+        if last.x < win_w / 2. {
+            points.push(Vec2::new(win_w / 2., last.y));
+        }
+
+        Terrain {
+            points,
+            flat,
+            win_w,
+            win_h,
+        }
+    }
+
+    /// Height of the surface at `x`. The top of a cliff where it is vertical
+    pub fn height_at(&self, x: f32) -> f32 {
+        let above = self.points.iter().position(|p| p.x > x);
+        match above {
+            None => self.points[self.points.len() - 1].y,
+            Some(0) => self.points[0].y,
+            Some(i) => {
+                let (a, b) = (self.points[i - 1], self.points[i]);
+                a.y + (b.y - a.y) * (x - a.x) / (b.x - a.x)
+            }
+        }
+    }
+
+    /// Where the level's own coordinates start: the foot of the tower,
+    /// wherever the terrain puts it
+    pub fn origin(&self) -> Vec2 {
+        let x = self.win_w / 4.;
+        Vec2::new(x, self.height_at(x))
+    }
+
+    /// Where the ball rests before it is launched
+    pub fn ball_start(&self) -> Vec2 {
+        let x = -self.win_w / 4.;
+        Vec2::new(x, self.height_at(x) + (30. / 2.))
+    }
+}
+
+/// Edges of the world. Anything leaving is gone for good: targets count as
+/// destroyed, blocks are removed and the ball's shot ends
+pub struct WorldBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Default for WorldBounds {
+    fn default() -> Self {
+        WorldBounds::new(&LevelData::default(), Vec2::ZERO, WIDTH, HEIGHT)
+    }
+}
+
+impl WorldBounds {
+    /// `origin` is where the level's coordinates start in the world
+    pub fn new(level: &LevelData, origin: Vec2, win_w: f32, win_h: f32) -> Self {
+        match &level.bounds {
+            Some(bounds) => WorldBounds {
+                min: origin + Vec2::new(bounds.left, bounds.bottom),
+                max: origin + Vec2::new(bounds.right, bounds.top),
+            },
+            None => WorldBounds {
+                min: Vec2::new(-win_w / 2. - WORLD_MARGIN, -win_h / 2. - WORLD_MARGIN),
+                max: Vec2::new(win_w / 2. + WORLD_MARGIN, win_h / 2. + WORLD_CEILING),
+            },
+        }
+    }
+
+    pub fn contains(&self, pos: Vec2) -> bool {
+        pos.cmpge(self.min).all() && pos.cmple(self.max).all()
+    }
+}
+
+/// Spawns the ground, a slab along the bottom of the window or the shape
+/// of the terrain down to the same depth
+pub fn spawn_ground(commands: &mut Commands, terrain: &Terrain) -> Entity {
+    let (win_w, win_h) = (terrain.win_w, terrain.win_h);
+    if terrain.flat {
+        return commands
+            .spawn()
+            .insert_bundle(lyon::GeometryBuilder::build_as(
+                &lyon::shapes::Rectangle {
+                    extents: Vec2::new(win_w, GROUND_HEIGHT * 2.),
+                    origin: lyon::shapes::RectangleOrigin::Center,
+                },
+                lyon::DrawMode::Outlined {
+                    fill_mode: lyon::FillMode::color(GROUND_COLOR),
+                    outline_mode: lyon::StrokeMode::color(GROUND_COLOR),
+                },
+                Transform::default(),
+            ))
+            .insert(RigidBody::Fixed)
+            .insert(Collider::cuboid(win_w / 2., GROUND_HEIGHT))
+            .insert(Transform::from_xyz(0.0, -win_h / 2., 0.0))
+            .insert(Ground)
+            .id();
+    }
+
+    let bottom = (-win_h / 2.) - GROUND_HEIGHT;
+    let mut outline = terrain.points.clone();
+    outline.push(Vec2::new(win_w / 2., bottom));
+    outline.push(Vec2::new(-win_w / 2., bottom));
+
+    commands
+        .spawn()
+        .insert_bundle(lyon::GeometryBuilder::build_as(
+            &lyon::shapes::Polygon {
+                points: outline,
+                closed: true,
+            },
+            lyon::DrawMode::Outlined {
+                fill_mode: lyon::FillMode::color(GROUND_COLOR),
+                outline_mode: lyon::StrokeMode::color(GROUND_COLOR),
+            },
+            Transform::default(),
+        ))
+        .insert(RigidBody::Fixed)
+        .insert(Collider::polyline(terrain.points.clone(), None))
+        .insert(Transform::default())
+        .insert(Ground)
+        .id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_levels::PointData;
+
+    fn terrain(points: &[(f32, f32)]) -> Terrain {
+        let level = LevelData {
+            terrain: points.iter().map(|&(x, y)| PointData { x, y }).collect(),
+            ..default()
+        };
+        Terrain::new(&level, 800., 600.)
+    }
+
+    #[test]
+    fn flat_ground_is_level_everywhere() {
+        let flat = terrain(&[]);
+        let ground = -300. + GROUND_HEIGHT;
+        assert!(flat.flat);
+        for x in [-400., 0., 123., 400.] {
+            assert_eq!(flat.height_at(x), ground);
+        }
+        assert_eq!(flat.origin(), Vec2::new(200., ground));
+    }
+
+    #[test]
+    fn slopes_are_interpolated_and_ends_carried_on() {
+        // level x 0 is at world x 200, level y 0 on the flat ground
+        let hill = terrain(&[(-100., 0.), (0., 50.), (100., 50.)]);
+        let ground = -300. + GROUND_HEIGHT;
+        assert!(!hill.flat);
+        assert_eq!(hill.height_at(-400.), ground);
+        assert_eq!(hill.height_at(150.), ground + 25.);
+        assert_eq!(hill.height_at(250.), ground + 50.);
+        assert_eq!(hill.height_at(400.), ground + 50.);
+        assert_eq!(hill.origin(), Vec2::new(200., ground + 50.));
+    }
+
+    #[test]
+    fn cliffs_give_the_top() {
+        let cliff = terrain(&[(-50., 0.), (-50., 80.)]);
+        let ground = -300. + GROUND_HEIGHT;
+        assert_eq!(cliff.height_at(149.), ground);
+        assert_eq!(cliff.height_at(150.), ground + 80.);
+        assert_eq!(cliff.height_at(151.), ground + 80.);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::AppState;
+use crate::actions::{Action, Actions};
+use crate::constants::{HEIGHT, UNDO_COST, WIDTH};
+use crate::environment::Environment;
+use crate::game::{
+    spawn_level_bodies, BodyId, CurrentLevel, Game, JointId, LaunchSystem, LevelNode, LevelStarted,
+    LevelState, LevelText, Paused, Run, ShotFired,
+};
+use crate::leaderboard::NameEntry;
+use crate::read_levels::LevelData;
+use crate::sound::{PlaySound, Sound};
+
+struct BodyState {
+    transform: Transform,
+    velocity: Velocity,
+}
+
+/// The level as it was when the last shot was fired
+pub struct Snapshot {
+    level: LevelData,
+    bodies: HashMap<BodyId, BodyState>,
+    joints: HashSet<JointId>,
+    env_time: f32,
+    shots_left: usize,
+    targets_left: usize,
+    run: Run,
+}
+
+/// Present for the frame between respawning the level and putting its
+/// bodies back where the snapshot has them
+struct Restoring;
+
+/// A shot was taken back
+pub struct ShotUndone;
+
+pub struct UndoPlugin;
+
+impl Plugin for UndoPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ShotUndone>()
+            .add_exit_system(AppState::InGame, clear_snapshot)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(AppState::InGame)
+                    .run_unless_resource_exists::<Paused>()
+                    .with_system(take_snapshot.into_conditional().after(LaunchSystem))
+                    .with_system(undo_shot.run_unless_resource_exists::<NameEntry>())
+                    .with_system(restore_snapshot.run_if_resource_exists::<Restoring>())
+                    .into(),
+            );
+    }
+}
+
+fn take_snapshot(
+    mut commands: Commands,
+    mut fired_events: EventReader<ShotFired>,
+    mut started_events: EventReader<LevelStarted>,
+    game: Res<Game>,
+    run: Res<Run>,
+    env: Res<Environment>,
+    level: Option<Res<CurrentLevel>>,
+    bodies: Query<(&BodyId, &Transform, &Velocity)>,
+    joints: Query<&JointId>,
+) {
+    // a shot on an earlier level can't be taken back
+    if started_events.iter().count() > 0 {
+        commands.remove_resource::<Snapshot>();
+    }
+
+    let level = match level {
+        Some(level) => level,
+        None => return,
+    };
+    if fired_events.iter().count() == 0 {
+        return;
+    }
+
+    let bodies = bodies
+        .iter()
+        .map(|(id, trans, vel)| {
+            let state = BodyState {
+                transform: *trans,
+                velocity: *vel,
+            };
+            (*id, state)
+        })
+        .collect();
+
+    commands.insert_resource(Snapshot {
+        level: level.0.clone(),
+        bodies,
+        joints: joints.iter().copied().collect(),
+        env_time: env.time,
+        // the launch has already used the shot
+        shots_left: game.shots_left + 1,
+        targets_left: game.targets_left,
+        run: run.clone(),
+    });
+}
+
+/// Rebuilds the level from its file once a missed shot is over, to be put
+/// back as it was by `restore_snapshot`
+fn undo_shot(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    mut game: ResMut<Game>,
+    mut run: ResMut<Run>,
+    snapshot: Option<Res<Snapshot>>,
+    level_nodes: Query<Entity, (With<LevelNode>, Without<LevelText>)>,
+    mut sounds: EventWriter<PlaySound>,
+    mut undone: EventWriter<ShotUndone>,
+) {
+    if !actions.just_pressed(Action::Undo) || game.undos_left == 0 {
+        return;
+    }
+    if !matches!(game.state, LevelState::Prelaunch | LevelState::Failed) {
+        return;
+    }
+    let snapshot = match snapshot {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+
+    for ent in level_nodes.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+    spawn_level_bodies(&mut commands, &snapshot.level, WIDTH, HEIGHT);
+    commands.insert_resource(Restoring);
+
+    game.state = LevelState::Prelaunch;
+    game.undos_left -= 1;
+    game.shots_left = snapshot.shots_left;
+    game.targets_left = snapshot.targets_left;
+    *run = snapshot.run.clone();
+    run.score = run.score.saturating_sub(UNDO_COST);
+    sounds.send(PlaySound(Sound::ButtonClick));
+    undone.send(ShotUndone);
+}
+
+fn restore_snapshot(
+    mut commands: Commands,
+    snapshot: Res<Snapshot>,
+    mut env: ResMut<Environment>,
+    mut bodies: Query<(Entity, &BodyId, &mut Transform, &mut Velocity)>,
+    joints: Query<(Entity, &JointId)>,
+) {
+    for (ent, id, mut trans, mut vel) in bodies.iter_mut() {
+        match snapshot.bodies.get(id) {
+            Some(state) => {
+                *trans = state.transform;
+                *vel = state.velocity;
+            }
+            // destroyed before the shot
+            None => commands.entity(ent).despawn_recursive(),
+        }
+    }
+
+    for (ent, id) in joints.iter() {
+        if !snapshot.joints.contains(id) {
+            commands.entity(ent).despawn();
+        }
+    }
+
+    env.time = snapshot.env_time;
+    commands.remove_resource::<Restoring>();
+    commands.remove_resource::<Snapshot>();
+}
+
+fn clear_snapshot(mut commands: Commands) {
+    commands.remove_resource::<Snapshot>();
+    commands.remove_resource::<Restoring>();
+}
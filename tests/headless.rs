@@ -0,0 +1,186 @@
+//! Levels played through `HeadlessLevel`, as the solver and the generator
+//! play them
+
+use bevy::prelude::Vec2;
+use trash_the_tower::constants::MAX_POWER;
+use trash_the_tower::headless::{settle_level, HeadlessLevel, Shot};
+use trash_the_tower::read_levels::{
+    BodyRef, JointData, MoverData, PathData, PointData, TargetData, TowerData,
+};
+use trash_the_tower::LevelData;
+
+/// The ball rests 450 pixels left of the foot of the tower
+const BALL_X: f32 = -450.;
+
+fn block(x: f32, y: f32, w: f32, h: f32) -> TowerData {
+    TowerData {
+        kind: "wood1".to_string(),
+        x,
+        y,
+        h,
+        w,
+        rotation: 0.,
+    }
+}
+
+fn target(x: f32, y: f32) -> TargetData {
+    TargetData { x, y, path: None }
+}
+
+/// A target on the ground just in front of the ball
+fn close_target() -> LevelData {
+    LevelData {
+        targets: vec![target(BALL_X + 60., 0.)],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn a_shot_at_a_target_clears_it() {
+    let mut sim = HeadlessLevel::new(&close_target());
+    assert_eq!(sim.targets_left(), 1);
+
+    let left = sim.fire(&Shot {
+        angle: 0.,
+        power: MAX_POWER / 2.,
+    });
+    assert_eq!(left, 0);
+}
+
+#[test]
+fn a_shot_away_from_the_target_misses() {
+    let mut sim = HeadlessLevel::new(&close_target());
+    let left = sim.fire(&Shot {
+        angle: 180.,
+        power: MAX_POWER / 2.,
+    });
+    assert_eq!(left, 1);
+}
+
+#[test]
+fn a_standing_tower_settles_as_it_is() {
+    let level = LevelData {
+        tower: vec![
+            block(-30., 0., 20., 60.),
+            block(10., 0., 20., 60.),
+            block(-30., 60., 60., 20.),
+        ],
+        targets: vec![target(0., 80.)],
+        ..Default::default()
+    };
+
+    let settled = settle_level(&level);
+    assert!(settled.rested);
+    assert!(!settled.collapsed);
+    assert_eq!(settled.targets_lost, 0);
+    assert_eq!(settled.level.tower.len(), 3);
+}
+
+#[test]
+fn a_floating_block_falls_while_settling() {
+    let level = LevelData {
+        tower: vec![block(-10., 200., 20., 20.)],
+        targets: vec![target(100., 0.)],
+        ..Default::default()
+    };
+
+    let settled = settle_level(&level);
+    assert!(settled.rested);
+    assert!(settled.collapsed);
+    assert!(settled.level.tower[0].y < 1.);
+}
+
+#[test]
+fn blocks_riding_a_mover_dont_hold_up_settling() {
+    // dropped onto a platform going round and round
+    let level = LevelData {
+        tower: vec![block(-10., 160., 20., 20.)],
+        targets: vec![target(100., 0.)],
+        movers: vec![MoverData {
+            x: 0.,
+            y: 100.,
+            w: 120.,
+            h: 20.,
+            path: Some(PathData {
+                points: vec![PointData { x: 100., y: 0. }, PointData { x: 100., y: 60. }],
+                speed: 30.,
+            }),
+            angular_speed: 0.,
+            pendulum: None,
+        }],
+        ..Default::default()
+    };
+
+    let mut sim = HeadlessLevel::spawn(&level);
+    sim.step(120);
+    for _ in 0..10 {
+        let before = sim.body_transforms();
+        sim.step(10);
+        let moved = before
+            .iter()
+            .zip(sim.body_transforms())
+            .any(|(a, b)| a.translation.distance(b.translation) > 1.);
+        assert!(moved && sim.settled());
+    }
+}
+
+#[test]
+fn a_slack_rope_lets_its_target_drop_until_taut() {
+    // hung 50 pixels below the pin on a rope twice as long
+    let level = LevelData {
+        targets: vec![target(0., 300.)],
+        joints: vec![JointData {
+            kind: "rope".to_string(),
+            a: BodyRef::Target(0),
+            b: None,
+            x: 0.,
+            y: 355.,
+            strength: None,
+            limits: None,
+            length: Some(100.),
+        }],
+        ..Default::default()
+    };
+
+    let mut sim = HeadlessLevel::spawn(&level);
+    sim.step(180);
+    let target = &sim.capture().targets[0];
+    let drop = 355. - (target.y + 5.);
+    assert!(
+        (95. ..105.).contains(&drop),
+        "hanging {} below the pin",
+        drop
+    );
+}
+
+#[test]
+fn a_rope_swings_its_target_round_the_pin() {
+    // held out level with the pin, then let go
+    let level = LevelData {
+        targets: vec![target(100., 295.)],
+        joints: vec![JointData {
+            kind: "rope".to_string(),
+            a: BodyRef::Target(0),
+            b: None,
+            x: 0.,
+            y: 300.,
+            strength: None,
+            limits: None,
+            length: None,
+        }],
+        ..Default::default()
+    };
+
+    let mut sim = HeadlessLevel::spawn(&level);
+    for _ in 0..6 {
+        sim.step(10);
+        let target = &sim.capture().targets[0];
+        let from_pin = Vec2::new(target.x, target.y + 5. - 300.);
+        assert!(
+            from_pin.length() < 102.,
+            "{} from the pin",
+            from_pin.length()
+        );
+        assert!(from_pin.y < 0.);
+    }
+}
@@ -0,0 +1,191 @@
+//! The whole game in an app without a window, driven and watched through its
+//! public events
+
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::ButtonState;
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use bevy::render::settings::WgpuSettings;
+use bevy::winit::WinitPlugin;
+use bevy_prototype_lyon::prelude::ShapePlugin;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+use trash_the_tower::cli::{Mode, Options};
+use trash_the_tower::constants::MAX_POWER;
+use trash_the_tower::editor::EditedLevel;
+use trash_the_tower::game::Levels;
+use trash_the_tower::read_levels::TargetData;
+use trash_the_tower::terrain::Terrain;
+use trash_the_tower::{
+    AppState, LaunchEvent, LevelCompleted, LevelData, LevelFailed, LevelStarted, ShotEnded,
+    ShotFired, TargetDestroyed, TrashTheTowerPlugins,
+};
+
+/// Everything the game announced, in order
+#[derive(Default)]
+struct Announced(Vec<String>);
+
+fn announce(
+    mut announced: ResMut<Announced>,
+    mut started: EventReader<LevelStarted>,
+    mut fired: EventReader<ShotFired>,
+    mut destroyed: EventReader<TargetDestroyed>,
+    mut ended: EventReader<ShotEnded>,
+    mut completed: EventReader<LevelCompleted>,
+    mut failed: EventReader<LevelFailed>,
+) {
+    let lines = started
+        .iter()
+        .map(|ev| format!("started {}", ev.level))
+        .chain(fired.iter().map(|_| "fired".to_string()))
+        .chain(destroyed.iter().map(|_| "destroyed".to_string()))
+        .chain(ended.iter().map(|ev| format!("ended {}", ev.shots_left)))
+        .chain(completed.iter().map(|ev| format!("completed {}", ev.level)))
+        .chain(failed.iter().map(|ev| format!("failed {}", ev.level)));
+    announced.0.extend(lines);
+}
+
+/// The game straight into a level with one target on the ground just in
+/// front of the ball. Nothing is drawn and physics steps a fixed 60th of a
+/// second each update, however long the update took
+fn app() -> App {
+    app_with(Options {
+        level: Some(0),
+        ..default()
+    })
+}
+
+fn app_with(options: Options) -> App {
+    let level = LevelData {
+        targets: vec![TargetData {
+            x: -390.,
+            y: 0.,
+            path: None,
+        }],
+        ..Default::default()
+    };
+
+    let mut app = App::new();
+    app.insert_resource(WgpuSettings {
+        backends: None,
+        ..default()
+    })
+    .insert_resource(options)
+    .insert_resource(Levels(vec![level]))
+    .insert_resource(RapierConfiguration {
+        timestep_mode: TimestepMode::Fixed {
+            dt: 1. / 60.,
+            substeps: 1,
+        },
+        ..default()
+    })
+    .init_resource::<Announced>()
+    // the logger can only be set up once for all the tests
+    .add_plugins_with(DefaultPlugins, |group| {
+        group.disable::<WinitPlugin>().disable::<LogPlugin>()
+    })
+    // its meshes need the renderer
+    .add_plugins_with(TrashTheTowerPlugins, |group| group.disable::<ShapePlugin>())
+    .add_system_to_stage(CoreStage::Last, announce);
+    app
+}
+
+/// Updates until `done` holds for what was announced, at most `frames` times
+fn run_until(app: &mut App, frames: usize, done: impl Fn(&[String]) -> bool) -> Vec<String> {
+    for _ in 0..frames {
+        app.update();
+        if done(&app.world.resource::<Announced>().0) {
+            break;
+        }
+    }
+    app.world.resource::<Announced>().0.clone()
+}
+
+fn press(app: &mut App, key: KeyCode) {
+    app.world.send_event(KeyboardInput {
+        scan_code: 0,
+        key_code: Some(key),
+        state: ButtonState::Pressed,
+    });
+    app.update();
+    app.world.send_event(KeyboardInput {
+        scan_code: 0,
+        key_code: Some(key),
+        state: ButtonState::Released,
+    });
+}
+
+fn state(app: &App) -> AppState {
+    app.world.resource::<CurrentState<AppState>>().0.clone()
+}
+
+fn launch(app: &mut App, angle: f32) {
+    let rad = angle.to_radians();
+    let start = app.world.resource::<Terrain>().ball_start();
+    let target = start + Vec2::new(rad.cos(), rad.sin()) * 100.;
+    app.world.send_event(LaunchEvent {
+        power: MAX_POWER / 2.,
+        target,
+    });
+}
+
+#[test]
+fn starts_in_the_level_asked_for() {
+    let mut app = app();
+    let announced = run_until(&mut app, 5, |a| !a.is_empty());
+
+    assert_eq!(announced, ["started 0"]);
+    assert_eq!(
+        app.world.resource::<CurrentState<AppState>>().0,
+        AppState::InGame
+    );
+}
+
+#[test]
+fn hitting_the_last_target_completes_the_level() {
+    let mut app = app();
+    run_until(&mut app, 5, |a| !a.is_empty());
+
+    launch(&mut app, 0.);
+    let announced = run_until(&mut app, 300, |a| {
+        a.iter().any(|l| l.starts_with("completed"))
+    });
+    assert_eq!(
+        announced,
+        ["started 0", "fired", "destroyed", "ended 0", "completed 0"]
+    );
+}
+
+#[test]
+fn missing_with_the_last_shot_fails_the_level() {
+    let mut app = app();
+    run_until(&mut app, 5, |a| !a.is_empty());
+
+    launch(&mut app, 135.);
+    let announced = run_until(&mut app, 1200, |a| {
+        a.iter().any(|l| l.starts_with("failed"))
+    });
+    assert_eq!(announced, ["started 0", "fired", "ended 0", "failed 0"]);
+}
+
+#[test]
+fn the_editor_playtests_its_level_and_comes_back_to_it() {
+    let mut app = app_with(Options {
+        mode: Mode::Editor,
+        level: Some(0),
+        ..default()
+    });
+    run_until(&mut app, 3, |_| false);
+    assert_eq!(state(&app), AppState::Editor);
+    assert_eq!(app.world.resource::<EditedLevel>().0.targets.len(), 1);
+
+    press(&mut app, KeyCode::Return);
+    let announced = run_until(&mut app, 5, |a| !a.is_empty());
+    assert_eq!(announced, ["started 0"]);
+    assert_eq!(state(&app), AppState::InGame);
+
+    press(&mut app, KeyCode::Escape);
+    run_until(&mut app, 3, |_| false);
+    assert_eq!(state(&app), AppState::Editor);
+    assert_eq!(app.world.resource::<EditedLevel>().0.targets.len(), 1);
+}